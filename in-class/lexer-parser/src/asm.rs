@@ -0,0 +1,74 @@
+/********************************************
+ * GrumpyVM assembly
+ ********************************************/
+
+/* The instructions of GrumpyVM, following doc/vm.md, extended with the
+   spawn and print instructions of pa/3.md. In assembly, push can also
+   take a label, which the assembler resolves to an instruction address. */
+
+pub type Address = usize;
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Val {
+    //Value types that may appear in GrumpyVM programs:
+    Vunit,          //The unit value
+    Vi32(i32),      //32-bit signed integers
+    Vbool(bool),    //Booleans
+    Vloc(u32),      //Stack or instruction locations
+    Vundef,         //The undefined value
+
+    //Value types that are used internally by the language implementation, and may not appear in GrumpyVM programs:
+    Vsize(i32),     //Metadata for heap objects that span multiple values
+    Vaddr(Address), //Pointers to heap locations
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Unop {
+    Neg, //Boolean negation
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Binop {
+    Add, //i32 addition
+    Mul, //i32 multiplication
+    Sub, //i32 subtraction
+    Div, //i32 division (raises an error on divide by zero)
+    Lt,  //Returns true if one i32 is less than another, otherwise false
+    Eq,  //Returns true if one i32 is equal another, otherwise false
+}
+
+//The operand of push
+#[derive(Debug,Clone,PartialEq)]
+pub enum Operand {
+    Val(Val),
+    Label(String),
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Instr {
+    Push(Operand), //Push(v): Push value v (or the address of a label) onto the stack
+    Pop,           //Pop a value from the stack, discarding it
+    Peek(u32),     //Peek(i): Push onto the stack the ith value from the top
+    Unary(Unop),   //Unary(u): Apply u to the top value on the stack
+    Binary(Binop), //Binary(b): Apply b to the top two values on the stack, replacing them with the result
+    Swap,          //Swap the top two values
+    Alloc,         //Allocate an array on the heap
+    Set,           //Write to a heap-allocated array
+    Get,           //Read from a heap-allocated array
+    Var(u32),      //Var(i): Get the value at stack position fp+i
+    Store(u32),    //Store(i): Store a value at stack position fp+i
+    SetFrame(u32), //SetFrame(i): Set fp = s.stack.len() - i
+    Call,          //Function call
+    Ret,           //Function return
+    Branch,        //Conditional jump
+    Halt,          //Halt the machine
+    Spawn,         //Spawn a thread running the closure on top of the stack
+    Print,         //Print the low-order byte of the i32 on top of the stack
+}
+
+//A line of an assembly program
+#[derive(Debug,Clone,PartialEq)]
+pub enum AsmLine {
+    Label(String), //Lmain:
+    Instr(Instr),
+}
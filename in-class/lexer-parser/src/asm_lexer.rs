@@ -0,0 +1,167 @@
+use lexer::{int_of_literal,LexError,LexerState,Lexeme,Span,Vocab};
+use lexer::LexError::*;
+
+/* Tokens of Grumpy assembly (.s files), following the grammar in
+   pa/1.md. Assembly is lexed by the same scanner and LexerState as
+   GrumpyIR, with AsmTok as the vocabulary. Comments and line/column
+   tracking therefore work exactly as they do for .gpy files. */
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum AsmTok {
+    //Instruction mnemonics
+    PUSH,
+    POP,
+    PEEK,
+    UNARY,
+    BINARY,
+    SWAP,
+    ALLOC,
+    SET,
+    GET,
+    VAR,
+    STORE,
+    SETFRAME,
+    CALL,
+    RET,
+    BRANCH,
+    HALT,
+    SPAWN,
+    PRINT,
+
+    //Values
+    TT,
+    UNDEF,
+    TRUE,
+    FALSE,
+    I32(i32),
+
+    //Unary and binary operations
+    NEG,
+    PLUS,
+    TIMES,
+    MINUS,
+    DIV,
+    LT,
+    EQ,
+
+    //Labels
+    LABEL(String),    //Lmain
+    LABELDEF(String), //Lmain:
+}
+
+use asm_lexer::AsmTok::*;
+
+pub type AsmLexer<'a> = LexerState<'a, AsmTok>;
+
+pub fn lexer<'a>(s: &'a str) -> AsmLexer<'a> {
+    LexerState::init(s)
+}
+
+//Is s a label, L[a-zA-Z0-9]+ or _L[a-zA-Z0-9]+?
+pub fn is_label(s: &str) -> bool {
+    let name =
+        if s.starts_with("_L") { s.split_at(2).1 }
+        else if s.starts_with('L') { s.split_at(1).1 }
+        else { return false };
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
+fn word(w: &str) -> Option<AsmTok> {
+    match w {
+        "push" => Some(PUSH),
+        "pop" => Some(POP),
+        "peek" => Some(PEEK),
+        "unary" => Some(UNARY),
+        "binary" => Some(BINARY),
+        "swap" => Some(SWAP),
+        "alloc" => Some(ALLOC),
+        "set" => Some(SET),
+        "get" => Some(GET),
+        "var" => Some(VAR),
+        "store" => Some(STORE),
+        "setframe" => Some(SETFRAME),
+        "call" => Some(CALL),
+        "ret" => Some(RET),
+        "branch" => Some(BRANCH),
+        "halt" => Some(HALT),
+        "spawn" => Some(SPAWN),
+        "print" => Some(PRINT),
+        "tt" => Some(TT),
+        "undef" => Some(UNDEF),
+        "true" => Some(TRUE),
+        "false" => Some(FALSE),
+        "neg" => Some(NEG),
+        _ if is_label(w) => Some(LABEL(w.to_string())),
+        _ => None
+    }
+}
+
+impl Vocab for AsmTok {
+    fn token(lx: Lexeme, text: &str, span: &Span) -> Result<AsmTok, LexError> {
+        match lx {
+            Lexeme::Ident =>
+                word(text).ok_or_else(|| UnknownWord(text.to_string(), span.clone())),
+            Lexeme::LabelDef => {
+                let name = text.trim_end_matches(':');
+                if is_label(name) { Ok(LABELDEF(name.to_string())) }
+                else { Err(UnknownWord(text.to_string(), span.clone())) }
+            },
+            Lexeme::Int => match int_of_literal(text) {
+                Some(i) => Ok(I32(i)),
+                None => Err(IntegerOverflow(text.to_string(), span.clone()))
+            },
+            Lexeme::Plus => Ok(PLUS),
+            Lexeme::Times => Ok(TIMES),
+            Lexeme::Minus => Ok(MINUS),
+            Lexeme::Div => Ok(DIV),
+            Lexeme::Lt => Ok(LT),
+            Lexeme::Eq => Ok(EQ),
+            _ => {
+                let c = text.chars().next().unwrap();
+                let end = span.start.advanced(text.split_at(1).0);
+                Err(UnexpectedChar(c, Span{start: span.start.clone(), end}))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::LineInfo;
+
+    fn pos(line_no: u64, col_no: u64, byte_offset: u64) -> LineInfo {
+        LineInfo{line_no, col_no, display_col: col_no, byte_offset}
+    }
+
+    #[test]
+    fn lex_asm() {
+        let s = "setframe 0\npush Lmain\ncall\nhalt\nLmain:\n  push -3 /* x */ push true\n\
+                 binary ==\npush _L3\nbranch\nunary neg\npeek 1 var 0 store 2 swap pop\n\
+                 push tt push undef push false alloc set get spawn print ret\n_L3:\nbinary <";
+        let mut l = lexer(s);
+        let mut toks = vec![];
+        while let Ok(tok) = l.next() { toks.push(tok) }
+        let label = |s: &str| LABEL(s.to_string());
+        assert_eq!(toks, vec![
+            SETFRAME, I32(0), PUSH, label("Lmain"), CALL, HALT, LABELDEF("Lmain".to_string()),
+            PUSH, I32(-3), PUSH, TRUE, BINARY, EQ, PUSH, label("_L3"), BRANCH, UNARY, NEG,
+            PEEK, I32(1), VAR, I32(0), STORE, I32(2), SWAP, POP,
+            PUSH, TT, PUSH, UNDEF, PUSH, FALSE, ALLOC, SET, GET, SPAWN, PRINT, RET,
+            LABELDEF("_L3".to_string()), BINARY, LT]);
+    }
+
+    #[test]
+    fn asm_errors() {
+        let mut l = lexer("push Lmain\n  pushh 3");
+        l.next().unwrap();
+        l.next().unwrap();
+        assert_eq!(l.next(), Err(UnknownWord("pushh".to_string(), Span{start: pos(2, 2, 13), end: pos(2, 7, 18)})));
+
+        for s in &["L", "_Lx_y", "main", "Lmain_"] {
+            assert!(matches!(lexer(s).next(), Err(UnknownWord(..))), "{}", s);
+        }
+        assert!(matches!(lexer("main:").next(), Err(UnknownWord(..))));
+        assert_eq!(lexer(" (").next(), Err(UnexpectedChar('(', Span{start: pos(1, 1, 1), end: pos(1, 2, 2)})));
+    }
+}
@@ -0,0 +1,191 @@
+use asm::*;
+use asm_lexer::{lexer,AsmLexer,AsmTok};
+use lexer::{LexError,Span};
+use parser::ParseError;
+
+/* GRAMMAR (see pa/1.md and pa/3.md):
+
+   <prog> ::= <il>*
+     <il> ::= <label>: | <instr>
+  <instr> ::= push <val> | push <label> | pop | peek <u32>
+            | unary <u> | binary <b> | swap | alloc | set | get
+            | var <u32> | store <u32> | setframe <u32>
+            | call | ret | branch | halt | spawn | print
+
+   Instructions needn't be on lines of their own. The lexer only lexes
+   integers that fit in an i32, so a u32 operand above i32::MAX reaches
+   the parser as an IntegerOverflow error holding its text. */
+
+type Res<T> = Result<T, ParseError>;
+
+fn err<T>(msg: String, span: Span) -> Res<T> {
+    Err(ParseError{msg, span})
+}
+
+//Describe the operand that was found instead of the one expected, for
+//a token or a character the lexer couldn't lex
+fn found(res: &Result<(AsmTok, Span), LexError>) -> String {
+    match res {
+        Ok((AsmTok::I32(i), _)) => i.to_string(),
+        Ok((tok, _)) => format!("{:?}", tok),
+        Err(LexError::UnexpectedChar(c, _)) => format!("'{}'", c),
+        Err(LexError::UnknownWord(w, _)) => format!("'{}'", w),
+        Err(LexError::IntegerOverflow(n, _)) => n.clone(),
+        Err(LexError::UnexpectedEof(_)) => "end of program".to_string(),
+        Err(err) => err.message()
+    }
+}
+
+//Report that what was expected wasn't found, at what was found instead
+fn expected<T>(what: &str, res: Result<(AsmTok, Span), LexError>) -> Res<T> {
+    let msg = format!("expected {}, found {}", what, found(&res));
+    match res {
+        Ok((_, span)) => err(msg, span),
+        Err(e) => err(msg, e.span().clone())
+    }
+}
+
+//The value of an integer literal that doesn't fit in an i32, if it fits
+//in a u32
+fn u32_of_literal(lit: &str) -> Option<u32> {
+    let (radix, digits) =
+        if lit.starts_with("0x") { (16, lit.split_at(2).1) }
+        else if lit.starts_with("0b") { (2, lit.split_at(2).1) }
+        else { (10, lit) };
+    u32::from_str_radix(digits, radix).ok()
+}
+
+fn parse_u32(l: &mut AsmLexer) -> Res<u32> {
+    let res = l.next_spanned();
+    if let Err(LexError::IntegerOverflow(ref text, _)) = res {
+        if let Some(n) = u32_of_literal(text) { return Ok(n) }
+    }
+    match res {
+        Ok((AsmTok::I32(i), _)) if i >= 0 => Ok(i as u32),
+        res => expected("an unsigned 32-bit integer", res)
+    }
+}
+
+fn parse_operand(l: &mut AsmLexer) -> Res<Operand> {
+    match l.next_spanned() {
+        Ok((AsmTok::TT, _)) => Ok(Operand::Val(Val::Vunit)),
+        Ok((AsmTok::UNDEF, _)) => Ok(Operand::Val(Val::Vundef)),
+        Ok((AsmTok::TRUE, _)) => Ok(Operand::Val(Val::Vbool(true))),
+        Ok((AsmTok::FALSE, _)) => Ok(Operand::Val(Val::Vbool(false))),
+        Ok((AsmTok::I32(i), _)) => Ok(Operand::Val(Val::Vi32(i))),
+        Ok((AsmTok::LABEL(l), _)) => Ok(Operand::Label(l)),
+        res => expected("a value or label", res)
+    }
+}
+
+fn parse_unop(l: &mut AsmLexer) -> Res<Unop> {
+    match l.next_spanned() {
+        Ok((AsmTok::NEG, _)) => Ok(Unop::Neg),
+        res => expected("a unary operation (neg)", res)
+    }
+}
+
+fn parse_binop(l: &mut AsmLexer) -> Res<Binop> {
+    match l.next_spanned() {
+        Ok((AsmTok::PLUS, _)) => Ok(Binop::Add),
+        Ok((AsmTok::TIMES, _)) => Ok(Binop::Mul),
+        Ok((AsmTok::MINUS, _)) => Ok(Binop::Sub),
+        Ok((AsmTok::DIV, _)) => Ok(Binop::Div),
+        Ok((AsmTok::LT, _)) => Ok(Binop::Lt),
+        Ok((AsmTok::EQ, _)) => Ok(Binop::Eq),
+        res => expected("a binary operation (+ * - / < ==)", res)
+    }
+}
+
+//Parse the operands, if any, of the instruction with mnemonic tok.
+fn parse_instr(l: &mut AsmLexer, tok: AsmTok, span: Span) -> Res<Instr> {
+    Ok(match tok {
+        AsmTok::PUSH => Instr::Push(parse_operand(l)?),
+        AsmTok::POP => Instr::Pop,
+        AsmTok::PEEK => Instr::Peek(parse_u32(l)?),
+        AsmTok::UNARY => Instr::Unary(parse_unop(l)?),
+        AsmTok::BINARY => Instr::Binary(parse_binop(l)?),
+        AsmTok::SWAP => Instr::Swap,
+        AsmTok::ALLOC => Instr::Alloc,
+        AsmTok::SET => Instr::Set,
+        AsmTok::GET => Instr::Get,
+        AsmTok::VAR => Instr::Var(parse_u32(l)?),
+        AsmTok::STORE => Instr::Store(parse_u32(l)?),
+        AsmTok::SETFRAME => Instr::SetFrame(parse_u32(l)?),
+        AsmTok::CALL => Instr::Call,
+        AsmTok::RET => Instr::Ret,
+        AsmTok::BRANCH => Instr::Branch,
+        AsmTok::HALT => Instr::Halt,
+        AsmTok::SPAWN => Instr::Spawn,
+        AsmTok::PRINT => Instr::Print,
+        tok => return expected("an instruction or label definition", Ok((tok, span)))
+    })
+}
+
+pub fn parse_asm(s: &str) -> Res<Vec<AsmLine>> {
+    let mut l = lexer(s);
+    let mut lines = vec![];
+    loop {
+        match l.next_spanned() {
+            Ok((AsmTok::LABELDEF(label), _)) => lines.push(AsmLine::Label(label)),
+            Ok((tok, span)) => lines.push(AsmLine::Instr(parse_instr(&mut l, tok, span)?)),
+            Err(LexError::UnexpectedEof(_)) => return Ok(lines),
+            res => return expected("an instruction or label definition", res)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn err_at(s: &str) -> (String, u64, u64, u64) {
+        let err = parse_asm(s).unwrap_err();
+        (err.msg, err.span.start.line_no, err.span.start.col_no, err.span.end.col_no)
+    }
+
+    #[test]
+    fn pa1_example() {
+        let s = "setframe 0\npush Lmain\ncall\nhalt\nLmain:\npush 3\npush 12\nbinary /\nret\n";
+        assert_eq!(parse_asm(s), Ok(vec![
+            AsmLine::Instr(Instr::SetFrame(0)),
+            AsmLine::Instr(Instr::Push(Operand::Label("Lmain".to_string()))),
+            AsmLine::Instr(Instr::Call),
+            AsmLine::Instr(Instr::Halt),
+            AsmLine::Label("Lmain".to_string()),
+            AsmLine::Instr(Instr::Push(Operand::Val(Val::Vi32(3)))),
+            AsmLine::Instr(Instr::Push(Operand::Val(Val::Vi32(12)))),
+            AsmLine::Instr(Instr::Binary(Binop::Div)),
+            AsmLine::Instr(Instr::Ret),
+        ]));
+    }
+
+    #[test]
+    fn every_instruction() {
+        let s = "push tt push undef push true push false push -7 push _L1\n\
+                 pop peek 4294967295 unary neg binary + binary * binary - binary < binary ==\n\
+                 swap alloc set get var 0x10 store 2 setframe 0b11 call ret branch halt spawn print";
+        let instrs: Vec<Instr> = parse_asm(s).unwrap().into_iter().map(|line| match line {
+            AsmLine::Instr(i) => i,
+            AsmLine::Label(l) => panic!("unexpected label {}", l)
+        }).collect();
+        assert_eq!(instrs.len(), 27);
+        assert_eq!(instrs[7], Instr::Peek(u32::MAX));
+        assert_eq!(instrs[18], Instr::Var(16));
+        assert_eq!(instrs[20], Instr::SetFrame(3));
+        assert_eq!(instrs[26], Instr::Print);
+    }
+
+    #[test]
+    fn malformed_operands() {
+        assert_eq!(err_at("push 1\n  peek -1"), ("expected an unsigned 32-bit integer, found -1".to_string(), 2, 7, 9));
+        assert_eq!(err_at("binary %"), ("expected a binary operation (+ * - / < ==), found '%'".to_string(), 1, 7, 8));
+        assert_eq!(err_at("binary neg"), ("expected a binary operation (+ * - / < ==), found NEG".to_string(), 1, 7, 10));
+        assert_eq!(err_at("unary +"), ("expected a unary operation (neg), found PLUS".to_string(), 1, 6, 7));
+        assert_eq!(err_at("var 4294967296"), ("expected an unsigned 32-bit integer, found 4294967296".to_string(), 1, 4, 14));
+        assert_eq!(err_at("push Lmain:"), ("expected a value or label, found LABELDEF(\"Lmain\")".to_string(), 1, 5, 11));
+        assert_eq!(err_at("push"), ("expected a value or label, found end of program".to_string(), 1, 4, 4));
+        assert_eq!(err_at("halt 3"), ("expected an instruction or label definition, found 3".to_string(), 1, 5, 6));
+        assert_eq!(err_at("halt\nfoo"), ("expected an instruction or label definition, found 'foo'".to_string(), 2, 0, 3));
+    }
+}
@@ -0,0 +1,184 @@
+use regex::Regex;
+use std::time::{Duration,Instant};
+
+use lexer::{LexerState,Tok};
+
+/* Benchmark harness comparing the table-driven scanner in lexer.rs
+   against the original recursive, Regex-based lexer (kept below as
+   the reference). Run with:
+
+     cargo run --release -- --bench-lex [terms]
+*/
+
+/********************************************
+ * Reference lexer
+ ********************************************/
+
+#[derive(Debug,Clone)]
+struct RefState<'a> {
+    comment_depth: u64,
+    rest: &'a str,
+    line_no: u64,
+    col_no: u64,
+}
+
+fn ref_lex(l: &mut RefState) -> Result<Tok, String> {
+    let s = l.rest;
+    if s.starts_with("/*") {
+        l.comment_depth += 1;
+        l.rest = s.split_at(2).1;
+        ref_lex(l)
+    }
+    else if s.starts_with("*/") {
+        l.comment_depth -= 1;
+        l.rest = s.split_at(2).1;
+        ref_lex(l)
+    }
+    else if s.starts_with(' ') || s.starts_with('\t') {
+        l.col_no += 1;
+        l.rest = s.split_at(1).1;
+        ref_lex(l)
+    }
+    else if s.starts_with("\r\n") || s.starts_with('\r') || s.starts_with('\n') {
+        l.col_no = 0;
+        l.line_no += 1;
+        l.rest = s.split_at(if s.starts_with("\r\n") { 2 } else { 1 }).1;
+        ref_lex(l)
+    }
+    else if s.starts_with('+') || s.starts_with('*') || s.starts_with('$') {
+        let tok = match s.as_bytes()[0] {
+            b'+' => Tok::PLUS,
+            b'*' => Tok::TIMES,
+            _ => Tok::DOLLAR
+        };
+        l.col_no += 1;
+        l.rest = s.split_at(1).1;
+        if l.comment_depth > 0 { ref_lex(l) } else { Ok(tok) }
+    }
+    else {
+        match Regex::new(r"^\A[[:digit:]]+").unwrap().find(s) {
+            Some(mat) => {
+                let (n, rest) = s.split_at(mat.end());
+                l.col_no += mat.end() as u64;
+                l.rest = rest;
+                if l.comment_depth > 0 { ref_lex(l) }
+                else { Ok(Tok::I32(n.parse::<i32>().unwrap())) }
+            },
+            None => {
+                if !s.is_empty() && l.comment_depth > 0 {
+                    l.col_no += 1;
+                    l.rest = s.split_at(1).1;
+                    ref_lex(l)
+                } else if !s.is_empty() {
+                    Err(format!(r"unexpected token '{}'", s.split_at(1).0))
+                } else {
+                    Err("unexpected end of program".to_string())
+                }
+            }
+        }
+    }
+}
+
+/* The reference lexer's token stream up to $, with the line and column
+   after each token. The reference doesn't count comment delimiters as
+   columns, while the table-driven scanner counts them as it does every
+   other character, so a token after a comment on its line is further
+   right in the scanner's stream, by the width of the delimiters. The
+   tokens and lines are the same. */
+fn ref_tokens(s: &str) -> Vec<(Tok, u64, u64)> {
+    let mut l = RefState{comment_depth: 0, rest: s.trim_end(), line_no: 1, col_no: 0};
+    let mut toks = vec![];
+    while let Ok(tok) = ref_lex(&mut l) {
+        toks.push((tok.clone(), l.line_no, l.col_no));
+        if tok == Tok::DOLLAR { break }
+    }
+    toks
+}
+
+fn dfa_tokens(s: &str) -> Vec<(Tok, u64, u64)> {
+    let mut l = LexerState::new(s);
+    let mut toks = vec![];
+    //Exercise peek as the parser does, before each next
+    while l.peek().is_ok() {
+        let tok = l.next().unwrap();
+        toks.push((tok.clone(), l.info.line_no, l.info.col_no));
+        if tok == Tok::DOLLAR { break }
+    }
+    toks
+}
+
+//The tokens and lines of a stream, without the columns
+fn lines(toks: &[(Tok, u64, u64)]) -> Vec<(Tok, u64)> {
+    toks.iter().map(|(tok, line, _)| (tok.clone(), *line)).collect()
+}
+
+/********************************************
+ * Driver
+ ********************************************/
+
+//An arithmetic expression with the given number of terms, interleaved
+//with comments and line breaks.
+fn gen_input(terms: usize) -> String {
+    let mut s = String::new();
+    for i in 0..terms {
+        if i > 0 { s.push_str(if i % 3 == 0 { " * " } else { " + " }) }
+        s.push_str(&(i % 1000).to_string());
+        if i % 7 == 0 { s.push_str(" /* term /* nested */ */") }
+        if i % 11 == 0 { s.push_str("\r\n") }
+    }
+    s.push_str(" $");
+    s
+}
+
+fn time<T, F: Fn() -> T>(f: F) -> (T, Duration) {
+    let start = Instant::now();
+    let res = f();
+    (res, start.elapsed())
+}
+
+pub fn run(terms: usize) {
+    let input = gen_input(terms);
+    println!("lexing {} bytes ({} terms)", input.len(), terms);
+    let (expected, ref_time) = time(|| ref_tokens(&input));
+    let (actual, dfa_time) = time(|| dfa_tokens(&input));
+    assert_eq!(lines(&expected), lines(&actual), "bench: token streams differ");
+    println!("tokens: {}", actual.len());
+    println!("reference (regex): {:?}", ref_time);
+    println!("table-driven:      {:?}", dfa_time);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::Tok::*;
+
+    const INPUTS: [&str; 6] = [
+        "1 + 2 * 3 $",
+        "  12\t+\t345 *\r\n6\r7\n\n + 8 $",
+        "0 * 007 + 2147483647 $",
+        "1 /* 2 + 3 */ + 4 $",
+        "/* a /* nested\r\n comment */ * / */ 5 /**/ + /* x */\n6 $",
+        "1 + 2 /* unterminated",
+    ];
+
+    #[test]
+    fn same_tokens_and_positions_as_reference() {
+        for s in INPUTS.iter() {
+            assert_eq!(lines(&dfa_tokens(s)), lines(&ref_tokens(s)), "{:?}", s);
+        }
+        assert_eq!(lines(&dfa_tokens(&gen_input(500))), lines(&ref_tokens(&gen_input(500))));
+        //Without comments, the columns match too
+        for s in INPUTS.iter().take(3) {
+            assert_eq!(dfa_tokens(s), ref_tokens(s), "{:?}", s);
+        }
+    }
+
+    //Tokens after a comment on the same line are further right than the
+    //reference has them, by the width of the delimiters
+    #[test]
+    fn counts_comment_delimiters_as_columns() {
+        let s = "1 /* 2 */ + /**/ 3\n4 $";
+        assert_eq!(dfa_tokens(s), vec![(I32(1), 1, 1), (PLUS, 1, 11), (I32(3), 1, 18), (I32(4), 2, 1), (DOLLAR, 2, 3)]);
+        assert_eq!(ref_tokens(s), vec![(I32(1), 1, 1), (PLUS, 1, 7), (I32(3), 1, 10), (I32(4), 2, 1), (DOLLAR, 2, 3)]);
+    }
+}
@@ -1,25 +1,98 @@
 use types::*;
 use types::Binop::*;
+use types::Unop::*;
 use types::Exp::*;
 use types::Instr::*;
 
-pub fn compile(e: &Exp) -> Vec<Instr> {
+//The instructions for e, which must be in the arithmetic language the
+//stack machine runs. Fails as Interp::interp does otherwise.
+pub fn compile(e: &Exp) -> Result<Vec<Instr>, EvalError> {
     //INVARIANT: e's result left on top of stack
-    match e {
+    Ok(match e {
         EI32(i) => vec![II32(*i)],
+        EUnop(u) => {
+            let mut is = vec![];
+            match u.op {
+                //- e is compiled as 0 - e
+                UMinus => {
+                    is.push(II32(0));
+                    is.append(&mut compile(&u.e)?);
+                    is.push(IMinus)
+                },
+                UNeg => {
+                    is.append(&mut compile(&u.e)?);
+                    is.push(INeg)
+                }
+            }
+            is
+        },
         EBinop(b) => {
-            let mut is_lhs = compile(&b.lhs);
-            let mut is_rhs = compile(&b.rhs);
+            let mut is_lhs = compile(&b.lhs)?;
+            let mut is_rhs = compile(&b.rhs)?;
             let mut is_op =
                 match b.op.clone() {
                     BPlus => vec![IPlus],
-                    BTimes => vec![ITimes]
+                    BTimes => vec![ITimes],
+                    BMinus => vec![IMinus],
+                    BDiv => vec![IDiv],
+                    BLt => vec![ILt],
+                    BEq => vec![IEq]
                 };
             let mut is = vec![];
             is.append(&mut is_lhs);
             is.append(&mut is_rhs);
             is.append(&mut is_op);
             is
+        },
+        EBool(b) => vec![II32(*b as i32)],
+        EError => return Err(EvalError::SyntaxError),
+        e => return Err(e.unsupported())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::parse;
+
+    #[test]
+    fn vm_agrees_with_interp() {
+        for s in ["1 + 2 * 3", "- (7 - 10) / 2", "neg (1 < 2) == neg 5", "3 - - 4 * 2"].iter() {
+            let e = parse(&format!("{} $", s)).unwrap();
+            assert_eq!(VM::init(&compile(&e).unwrap()).run(), e.interp(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn overflow_modes() {
+        use types::OverflowMode::*;
+        let cases = [
+            ("2147483647 + 1", Err(EvalError::Overflow(BPlus)), Ok(i32::MIN), Ok(i32::MAX)),
+            ("- -2147483648", Err(EvalError::Overflow(BMinus)), Ok(i32::MIN), Ok(i32::MAX)),
+            ("-2147483648 / -1", Err(EvalError::Overflow(BDiv)), Ok(i32::MIN), Ok(i32::MAX)),
+            ("65536 * -65536 < 0", Err(EvalError::Overflow(BTimes)), Ok(0), Ok(1)),
+            ("1 / (2 - 2)", Err(EvalError::DivByZero), Err(EvalError::DivByZero), Err(EvalError::DivByZero)),
+        ];
+        for (s, trap, wrap, saturate) in cases.iter() {
+            let e = parse(s).unwrap();
+            for (mode, expected) in [(Trap, trap), (Wrap, wrap), (Saturate, saturate)].iter() {
+                assert_eq!(&e.interp_with(*mode), *expected, "{} in {:?} mode", s, mode);
+                assert_eq!(&VM::with_mode(&compile(&e).unwrap(), *mode).run(), *expected, "{} in {:?} mode (VM)", s, mode);
+            }
+        }
+        assert_eq!(EError.interp(), Err(EvalError::SyntaxError));
+    }
+
+    #[test]
+    fn unsupported_forms() {
+        use ir_parser::parse_program;
+        assert_eq!(compile(&EError).err(), Some(EvalError::SyntaxError));
+        let cases = [("(let x 1 x)", "let"), ("(seq 1 2)", "seq"), ("(cond true 1 2)", "cond"),
+                     ("(- 0 (alloc 1 0))", "alloc"), ("tt", "tt"), ("(+ 1 x)", "variable x")];
+        for (s, form) in cases.iter() {
+            let e = parse_program(&format!("% {}", s)).unwrap().main.lower();
+            assert_eq!(compile(&e).err(), Some(EvalError::Unsupported(form.to_string())), "{}", s);
+            assert_eq!(compile(&e).err(), e.interp().err(), "{}", s);
         }
     }
 }
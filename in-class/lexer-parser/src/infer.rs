@@ -0,0 +1,526 @@
+use std::collections::HashSet;
+
+use lexer::Span;
+use source::*;
+use typecheck::TypeError;
+use types::Binop::*;
+use types::Unop::*;
+
+/********************************************
+ * Type inference for the source language
+ ********************************************/
+
+/* Hindley-Milner type inference for the source language, so that the
+   annotations doc/source.md requires on lams may be left out.
+
+   Each unannotated lam parameter is given a fresh type variable, and
+   the typing rules of doc/source.md become equations between types,
+   which are solved by unification as they're generated. The rules are
+   those of doc/source.md, with the first premise T-let omits, G |- e1 :
+   ty1, and with e1 in (seq e1 e2) allowed any type, as in typecheck.rs.
+
+   Let-bound variables are polymorphic: (let x e1 e2) generalizes the
+   type of e1 over the variables that aren't free in the environment,
+   and each use of x in e2 instantiates them afresh, so
+
+     (let id (lam x x) (cond (app id true) (app id 1) 2))
+
+   is well-typed. That's only sound when e1 can't allocate, though. In
+
+     (let r (alloc 1 (lam x x)) (seq (set r 0 (lam y (+ y 1))) (app (get r 0) true)))
+
+   generalizing r : (array (-> 'a 'a)) would let it be stored into at
+   (-> i32 i32) and read back at (-> bool bool). So e1 is generalized
+   only if it's nonexpansive, if evaluating it can't alloc or apply a
+   function (which might alloc); otherwise its type variables stay free
+   and are fixed by the uses of x. The program as a whole is generalized
+   in the same way.
+
+   The inferred types are printed back into the annotated syntax by
+   filling in each lam's annotation. Type variables nothing constrains,
+   like y's in (app (lam x tt) (lam y y)), are defaulted to unit, as any
+   type would do. A parameter whose type is polymorphic can't be written
+   down, since annotations have no type variables, so its lam is left
+   unannotated. */
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Type {
+    Var(usize),
+    I32,
+    Bool,
+    Unit,
+    Array(Box<Type>),
+    Fun(Box<Type>, Box<Type>),
+}
+
+impl Type {
+    pub fn from_source(ty: &SourceTy) -> Type {
+        match ty {
+            SourceTy::I32 => Type::I32,
+            SourceTy::Bool => Type::Bool,
+            SourceTy::Unit => Type::Unit,
+            SourceTy::Array(t) => Type::Array(Box::new(Type::from_source(t))),
+            SourceTy::Fun(t1, t2) => Type::Fun(Box::new(Type::from_source(t1)), Box::new(Type::from_source(t2)))
+        }
+    }
+
+    //The annotation for this type, if it has no type variables
+    pub fn to_source(&self) -> Option<SourceTy> {
+        match self {
+            Type::Var(_) => None,
+            Type::I32 => Some(SourceTy::I32),
+            Type::Bool => Some(SourceTy::Bool),
+            Type::Unit => Some(SourceTy::Unit),
+            Type::Array(t) => Some(SourceTy::Array(Box::new(t.to_source()?))),
+            Type::Fun(t1, t2) => Some(SourceTy::Fun(Box::new(t1.to_source()?), Box::new(t2.to_source()?)))
+        }
+    }
+
+    fn vars(&self, vars: &mut Vec<usize>) {
+        match self {
+            Type::Var(a) => if !vars.contains(a) { vars.push(*a) },
+            Type::I32 | Type::Bool | Type::Unit => {},
+            Type::Array(t) => t.vars(vars),
+            Type::Fun(t1, t2) => { t1.vars(vars); t2.vars(vars) }
+        }
+    }
+
+    //Replace each variable a with f(a)
+    fn map_vars<F: Fn(usize) -> Type>(&self, f: &F) -> Type {
+        match self {
+            Type::Var(a) => f(*a),
+            Type::I32 | Type::Bool | Type::Unit => self.clone(),
+            Type::Array(t) => Type::Array(Box::new(t.map_vars(f))),
+            Type::Fun(t1, t2) => Type::Fun(Box::new(t1.map_vars(f)), Box::new(t2.map_vars(f)))
+        }
+    }
+
+    //Print, naming variables 'a, 'b, ... in the order names has them,
+    //adding those it doesn't
+    fn show(&self, names: &mut Vec<usize>) -> String {
+        match self {
+            Type::Var(a) => {
+                let i = names.iter().position(|b| b == a).unwrap_or_else(|| { names.push(*a); names.len() - 1 });
+                let letter = (b'a' + (i % 26) as u8) as char;
+                if i < 26 { format!("'{}", letter) } else { format!("'{}{}", letter, i / 26) }
+            },
+            Type::I32 => "i32".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::Unit => "unit".to_string(),
+            Type::Array(t) => format!("(array {})", t.show(names)),
+            Type::Fun(t1, t2) => {
+                let s1 = t1.show(names);
+                format!("(-> {} {})", s1, t2.show(names))
+            }
+        }
+    }
+}
+
+//Prints the annotation syntax, with type variables 'a, 'b, ...
+impl ToString for Type {
+    fn to_string(&self) -> String {
+        self.show(&mut vec![])
+    }
+}
+
+//The type of a let-bound variable: ty, for any types for vars
+#[derive(Debug,Clone)]
+struct Scheme {
+    vars: Vec<usize>,
+    ty: Type,
+}
+
+impl Scheme {
+    fn mono(ty: Type) -> Scheme {
+        Scheme{vars: vec![], ty}
+    }
+}
+
+enum Mismatch {
+    Different,
+    //Unifying would make a type contain itself
+    Infinite,
+}
+
+//Whether evaluating e might alloc
+fn expansive(e: &SourceExp) -> bool {
+    match &e.kind {
+        SourceKind::Alloc(..) | SourceKind::App(..) => true,
+        SourceKind::I32(_) | SourceKind::Bool(_) | SourceKind::Unit | SourceKind::Var(_) | SourceKind::Lam(..) => false,
+        SourceKind::Unop(_, e) => expansive(e),
+        SourceKind::Binop(_, e1, e2) | SourceKind::Let(_, e1, e2) | SourceKind::Seq(e1, e2) | SourceKind::Get(e1, e2) =>
+            expansive(e1) || expansive(e2),
+        SourceKind::Set(e1, e2, e3) | SourceKind::Cond(e1, e2, e3) => expansive(e1) || expansive(e2) || expansive(e3)
+    }
+}
+
+struct Infer {
+    //subst[a] is the type variable a stands for, once that's known
+    subst: Vec<Option<Type>>,
+    //Variables some let generalized
+    generalized: HashSet<usize>,
+    errors: Vec<TypeError>,
+    //Variables in scope, innermost last
+    env: Vec<(String, Scheme)>,
+    //The parameter types of the lams, in the order they're reached
+    params: Vec<Type>,
+}
+
+impl Infer {
+    fn fresh(&mut self) -> Type {
+        self.subst.push(None);
+        Type::Var(self.subst.len() - 1)
+    }
+
+    //ty with the variables that are known substituted
+    fn resolve(&self, ty: &Type) -> Type {
+        ty.map_vars(&|a| match self.subst[a] {
+            Some(ref t) => self.resolve(t),
+            None => Type::Var(a)
+        })
+    }
+
+    //ty, or if it's a known variable what that stands for
+    fn head(&self, ty: &Type) -> Type {
+        match ty {
+            Type::Var(a) => match self.subst[*a] {
+                Some(ref t) => self.head(t),
+                None => ty.clone()
+            },
+            _ => ty.clone()
+        }
+    }
+
+    fn unify(&mut self, t1: &Type, t2: &Type) -> Result<(), Mismatch> {
+        match (self.head(t1), self.head(t2)) {
+            (Type::Var(a), Type::Var(b)) if a == b => Ok(()),
+            (Type::Var(a), t) | (t, Type::Var(a)) => {
+                let mut vars = vec![];
+                self.resolve(&t).vars(&mut vars);
+                if vars.contains(&a) { return Err(Mismatch::Infinite) }
+                self.subst[a] = Some(t);
+                Ok(())
+            },
+            (Type::I32, Type::I32) | (Type::Bool, Type::Bool) | (Type::Unit, Type::Unit) => Ok(()),
+            (Type::Array(t1), Type::Array(t2)) => self.unify(&t1, &t2),
+            (Type::Fun(p1, r1), Type::Fun(p2, r2)) => {
+                self.unify(&p1, &p2)?;
+                self.unify(&r1, &r2)
+            },
+            _ => Err(Mismatch::Different)
+        }
+    }
+
+    fn error(&mut self, msg: String, span: &Span) {
+        self.errors.push(TypeError{msg, span: span.clone()})
+    }
+
+    //Unify expected with found, the type of the expression at span
+    fn expect(&mut self, expected: &Type, found: &Type, span: &Span) {
+        if let Err(mismatch) = self.unify(expected, found) {
+            let mut names = vec![];
+            let expected = self.resolve(expected).show(&mut names);
+            let found = self.resolve(found).show(&mut names);
+            let msg = match mismatch {
+                Mismatch::Different => format!("expected {}, found {}", expected, found),
+                Mismatch::Infinite => format!("expected {}, found {}, which would make an infinite type", expected, found)
+            };
+            self.error(msg, span)
+        }
+    }
+
+    fn check(&mut self, e: &SourceExp, expected: &Type) {
+        let ty = self.infer(e);
+        self.expect(expected, &ty, &e.span)
+    }
+
+    //The scheme for a let-bound variable whose definition e has type ty
+    fn generalize(&mut self, e: &SourceExp, ty: Type) -> Scheme {
+        let ty = self.resolve(&ty);
+        if expansive(e) { return Scheme::mono(ty) }
+        let mut free = vec![];
+        for (_, scheme) in self.env.iter() {
+            let mut vars = vec![];
+            self.resolve(&scheme.ty).vars(&mut vars);
+            free.extend(vars.into_iter().filter(|a| !scheme.vars.contains(a)));
+        }
+        let mut vars = vec![];
+        ty.vars(&mut vars);
+        vars.retain(|a| !free.contains(a));
+        self.generalized.extend(vars.iter().cloned());
+        Scheme{vars, ty}
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let fresh: Vec<(usize, Type)> = scheme.vars.iter().map(|a| (*a, self.fresh())).collect();
+        scheme.ty.map_vars(&|a| match fresh.iter().find(|(b, _)| *b == a) {
+            Some((_, t)) => t.clone(),
+            None => Type::Var(a)
+        })
+    }
+
+    //The type of e. An expression with an error is given a fresh
+    //variable, which the expressions that use it can make any type.
+    fn infer(&mut self, e: &SourceExp) -> Type {
+        match &e.kind {
+            SourceKind::I32(_) => Type::I32,
+            SourceKind::Bool(_) => Type::Bool,
+            SourceKind::Unit => Type::Unit,
+            SourceKind::Var(x) => match self.env.iter().rev().find(|(y, _)| y == x) {
+                Some((_, scheme)) => {
+                    let scheme = scheme.clone();
+                    self.instantiate(&scheme)
+                },
+                None => {
+                    self.error(format!("unbound variable {}", x), &e.span);
+                    self.fresh()
+                }
+            },
+            SourceKind::Unop(op, e) => {
+                let ty = match op { UMinus => Type::I32, UNeg => Type::Bool };
+                self.check(e, &ty);
+                ty
+            },
+            SourceKind::Binop(op, e1, e2) => {
+                self.check(e1, &Type::I32);
+                self.check(e2, &Type::I32);
+                match op {
+                    BPlus | BTimes | BMinus | BDiv => Type::I32,
+                    BLt | BEq => Type::Bool
+                }
+            },
+            SourceKind::Let(x, e1, e2) => {
+                let ty1 = self.infer(e1);
+                let scheme = self.generalize(e1, ty1);
+                self.env.push((x.clone(), scheme));
+                let ty2 = self.infer(e2);
+                self.env.pop();
+                ty2
+            },
+            SourceKind::Seq(e1, e2) => {
+                self.infer(e1);
+                self.infer(e2)
+            },
+            SourceKind::Alloc(esize, einit) => {
+                self.check(esize, &Type::I32);
+                Type::Array(Box::new(self.infer(einit)))
+            },
+            SourceKind::Set(earr, eidx, e) => {
+                let ty = self.fresh();
+                self.check(earr, &Type::Array(Box::new(ty.clone())));
+                self.check(eidx, &Type::I32);
+                self.check(e, &ty);
+                Type::Unit
+            },
+            SourceKind::Get(earr, eidx) => {
+                let ty = self.fresh();
+                self.check(earr, &Type::Array(Box::new(ty.clone())));
+                self.check(eidx, &Type::I32);
+                ty
+            },
+            SourceKind::Cond(econd, e1, e2) => {
+                self.check(econd, &Type::Bool);
+                let ty = self.infer(e1);
+                self.check(e2, &ty);
+                ty
+            },
+            SourceKind::Lam(x, ann, body) => {
+                let param = match ann {
+                    Some(ty) => Type::from_source(ty),
+                    None => self.fresh()
+                };
+                self.params.push(param.clone());
+                self.env.push((x.clone(), Scheme::mono(param.clone())));
+                let ret = self.infer(body);
+                self.env.pop();
+                Type::Fun(Box::new(param), Box::new(ret))
+            },
+            SourceKind::App(e1, e2) => {
+                let ty = self.infer(e1);
+                let (param, ret) = match self.head(&ty) {
+                    Type::Fun(param, ret) => (*param, *ret),
+                    Type::Var(_) => {
+                        let (param, ret) = (self.fresh(), self.fresh());
+                        self.expect(&ty, &Type::Fun(Box::new(param.clone()), Box::new(ret.clone())), &e1.span);
+                        (param, ret)
+                    },
+                    ty => {
+                        let msg = format!("expected a function, found {}", self.resolve(&ty).to_string());
+                        self.error(msg, &e1.span);
+                        self.infer(e2);
+                        return self.fresh()
+                    }
+                };
+                self.check(e2, &param);
+                ret
+            }
+        }
+    }
+
+    //ty, with the variables no let generalized defaulted to unit
+    fn default(&self, ty: &Type) -> Type {
+        self.resolve(ty).map_vars(&|a| if self.generalized.contains(&a) { Type::Var(a) } else { Type::Unit })
+    }
+
+    //e with the annotations of its lams filled in, params being the
+    //parameter types of the lams left in e
+    fn annotate(&self, e: &SourceExp, params: &mut ::std::slice::Iter<Type>) -> SourceExp {
+        //A lam's parameter comes before those of the lams in its body
+        let param = match e.kind {
+            SourceKind::Lam(..) => params.next(),
+            _ => None
+        };
+        let mut go = |e: &SourceExp| Box::new(self.annotate(e, params));
+        let kind = match &e.kind {
+            SourceKind::I32(_) | SourceKind::Bool(_) | SourceKind::Unit | SourceKind::Var(_) => e.kind.clone(),
+            SourceKind::Unop(op, e) => SourceKind::Unop(op.clone(), go(e)),
+            SourceKind::Binop(op, e1, e2) => {
+                let e1 = go(e1);
+                SourceKind::Binop(op.clone(), e1, go(e2))
+            },
+            SourceKind::Let(x, e1, e2) => {
+                let e1 = go(e1);
+                SourceKind::Let(x.clone(), e1, go(e2))
+            },
+            SourceKind::Seq(e1, e2) => {
+                let e1 = go(e1);
+                SourceKind::Seq(e1, go(e2))
+            },
+            SourceKind::Alloc(e1, e2) => {
+                let e1 = go(e1);
+                SourceKind::Alloc(e1, go(e2))
+            },
+            SourceKind::Set(e1, e2, e3) => {
+                let (e1, e2) = (go(e1), go(e2));
+                SourceKind::Set(e1, e2, go(e3))
+            },
+            SourceKind::Get(e1, e2) => {
+                let e1 = go(e1);
+                SourceKind::Get(e1, go(e2))
+            },
+            SourceKind::Cond(e1, e2, e3) => {
+                let (e1, e2) = (go(e1), go(e2));
+                SourceKind::Cond(e1, e2, go(e3))
+            },
+            SourceKind::Lam(x, ann, body) => {
+                let param = param.expect("annotate: a lam inference didn't reach");
+                let ann = match ann {
+                    Some(ty) => Some(ty.clone()),
+                    None => self.default(param).to_source()
+                };
+                SourceKind::Lam(x.clone(), ann, go(body))
+            },
+            SourceKind::App(e1, e2) => {
+                let e1 = go(e1);
+                SourceKind::App(e1, go(e2))
+            }
+        };
+        SourceExp{kind, span: e.span.clone()}
+    }
+}
+
+//A program's inferred type, and the program with its lams annotated
+#[derive(Debug,Clone,PartialEq)]
+pub struct Inferred {
+    pub ty: Type,
+    pub exp: SourceExp,
+}
+
+pub fn infer(e: &SourceExp) -> Result<Inferred, Vec<TypeError>> {
+    let mut inf = Infer{subst: vec![], generalized: HashSet::new(), errors: vec![], env: vec![], params: vec![]};
+    let ty = inf.infer(e);
+    if !inf.errors.is_empty() { return Err(inf.errors) }
+    let ty = inf.generalize(e, ty).ty;
+    let exp = inf.annotate(e, &mut inf.params.iter());
+    Ok(Inferred{ty: inf.default(&ty), exp})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use source_parser::parse_source;
+
+    fn infer_str(s: &str) -> Inferred {
+        infer(&parse_source(s).unwrap()).unwrap_or_else(|errs| panic!("{}: {}", s, errs[0]))
+    }
+
+    fn errors(s: &str) -> Vec<(String, u64, u64)> {
+        match infer(&parse_source(s).unwrap()) {
+            Ok(inferred) => panic!("{} has type {}", s, inferred.ty.to_string()),
+            Err(errs) => errs.into_iter().map(|err| (err.msg, err.span.start.line_no, err.span.start.col_no)).collect()
+        }
+    }
+
+    fn err(msg: &str, line: u64, col: u64) -> (String, u64, u64) {
+        (msg.to_string(), line, col)
+    }
+
+    #[test]
+    fn infers_types() {
+        let cases = [
+            ("(+ 1 2)", "i32"),
+            ("(lam x (+ x 1))", "(-> i32 i32)"),
+            ("(lam x x)", "(-> 'a 'a)"),
+            ("(lam f (lam x (app f (app f x))))", "(-> (-> 'a 'a) (-> 'a 'a))"),
+            ("(lam a (lam i (get a i)))", "(-> (array 'a) (-> i32 'a))"),
+            ("(lam x (lam y (seq (set x 0 y) x)))", "(-> (array 'a) (-> 'a (array 'a)))"),
+            ("(let id (lam x x) (cond (app id true) (app id 1) 2))", "i32"),
+            ("(let k (lam x (lam y x)) (app (app k tt) 3))", "unit"),
+            ("(lam x i32 (lam y y))", "(-> i32 (-> 'a 'a))"),
+            //Expansive, so nothing's generalized
+            ("(alloc 3 (lam x x))", "(array (-> unit unit))"),
+            ("(app (lam x tt) (lam y y))", "unit"),
+        ];
+        for (s, ty) in cases.iter() {
+            assert_eq!(infer_str(s).ty.to_string(), *ty, "{}", s);
+        }
+    }
+
+    #[test]
+    fn value_restriction() {
+        let s = "(let r (alloc 1 (lam x x)) (seq (set r 0 (lam y (+ y 1))) (app (get r 0) true)))";
+        assert_eq!(errors(s), vec![err("expected i32, found bool", 1, 73)]);
+        //A function that allocs is still polymorphic, since each call
+        //allocs a new array
+        let s = "(let mk (lam u (alloc 1 (lam x x))) (seq (set (app mk tt) 0 (lam y (+ y 1))) (app (get (app mk tt) 0) true)))";
+        assert_eq!(infer_str(s).ty, Type::Bool);
+    }
+
+    #[test]
+    fn prints_annotations() {
+        let cases = [
+            ("(lam x (+ x 1))", "(lam x i32 (+ x 1))"),
+            ("(lam f (lam x (app f (< x 0))))", "(lam f (lam x i32 (app f (< x 0))))"),
+            ("(app (lam f (app f 2)) (lam x (neg (== x 0))))", "(app (lam f (-> i32 bool) (app f 2)) (lam x i32 (neg (== x 0))))"),
+            ("(let f (lam a (lam i (get a i))) (app (app f (alloc 2 true)) 0))",
+             "(let f (lam a (lam i i32 (get a i))) (app (app f (alloc 2 true)) 0))"),
+            ("(let r (alloc 1 (lam x x)) (seq (set r 0 (lam y (+ y 1))) r))",
+             "(let r (alloc 1 (lam x i32 x)) (seq (set r 0 (lam y i32 (+ y 1))) r))"),
+            ("(app (lam x tt) (lam y bool y))", "(app (lam x (-> bool bool) tt) (lam y bool y))"),
+        ];
+        for (s, annotated) in cases.iter() {
+            let inferred = infer_str(s);
+            assert_eq!(inferred.exp.to_string(), *annotated);
+            assert_eq!(infer_str(annotated).ty.to_string(), inferred.ty.to_string(), "{}", annotated);
+        }
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert_eq!(errors("(+ 1 true)"), vec![err("expected i32, found bool", 1, 5)]);
+        assert_eq!(errors("(lam x i32 (neg x))"), vec![err("expected bool, found i32", 1, 16)]);
+        assert_eq!(errors("(cond true 1 tt)"), vec![err("expected i32, found unit", 1, 13)]);
+        assert_eq!(errors("(app 1 2)"), vec![err("expected a function, found i32", 1, 5)]);
+        assert_eq!(errors("(let x y x)"), vec![err("unbound variable y", 1, 7)]);
+        assert_eq!(errors("(lam x (lam y (seq (set x 0 y) (get x true))))"), vec![err("expected i32, found bool", 1, 38)]);
+        assert_eq!(errors("(lam f (app f f))"),
+                   vec![err("expected 'a, found (-> 'a 'b), which would make an infinite type", 1, 14)]);
+        //The annotation is checked, not replaced
+        assert_eq!(errors("(app (lam x bool x) 1)"), vec![err("expected bool, found i32", 1, 20)]);
+        //Checking carries on after an error
+        assert_eq!(errors("(seq (+ 1 tt)\n  (app (lam x (neg x)) 2))"), vec![
+            err("expected i32, found unit", 1, 10),
+            err("expected bool, found i32", 2, 23),
+        ]);
+    }
+}
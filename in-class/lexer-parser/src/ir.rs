@@ -0,0 +1,88 @@
+use lexer::Span;
+use types;
+use types::{Binexp,Binop,Ty,Unexp,Unop};
+
+/********************************************
+ * GrumpyIR abstract syntax
+ ********************************************/
+
+/* The syntax of whole GrumpyIR programs, as described in doc/ir.md.
+   Every node carries the span of the source text it was parsed from. */
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Program {
+    pub funs: Vec<FunDef>,
+    pub main: Exp,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct FunDef {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub ret_ty: Ty,
+    pub body: Exp,
+    pub span: Span,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub ty: Ty,
+    pub span: Span,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Exp {
+    pub kind: ExpKind,
+    pub span: Span,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum ExpKind {
+    I32(i32),
+    Bool(bool),
+    Unit,
+    Var(String),
+    Unop(Unop, Box<Exp>),
+    Binop(Binop, Box<Exp>, Box<Exp>),
+    Let(String, Box<Exp>, Box<Exp>),
+    Seq(Box<Exp>, Box<Exp>),
+    Alloc(Box<Exp>, Box<Exp>),
+    Set(Box<Exp>, Box<Exp>, Box<Exp>),
+    Get(Box<Exp>, Box<Exp>),
+    Cond(Box<Exp>, Box<Exp>, Box<Exp>),
+    Funptr(String),
+    //(call e e1 ... eN), calling a function pointer
+    Call(Box<Exp>, Vec<Exp>),
+    //(f e1 ... eN), calling function f directly
+    CallFun(String, Vec<Exp>),
+    Print(Box<Exp>),
+    Spawn(Box<Exp>),
+}
+
+impl Exp {
+    //The expression without spans, in the core syntax of types.rs
+    pub fn lower(&self) -> types::Exp {
+        use types::Exp::*;
+        let lower = |e: &Exp| Box::new(e.lower());
+        match &self.kind {
+            ExpKind::I32(i) => EI32(*i),
+            ExpKind::Bool(b) => EBool(*b),
+            ExpKind::Unit => EUnit,
+            ExpKind::Var(x) => EId(x.clone()),
+            ExpKind::Unop(op, e) => EUnop(Box::new(Unexp{op: op.clone(), e: e.lower()})),
+            ExpKind::Binop(op, e1, e2) => EBinop(Box::new(Binexp{op: op.clone(), lhs: e1.lower(), rhs: e2.lower()})),
+            ExpKind::Let(x, e1, e2) => ELet(x.clone(), lower(e1), lower(e2)),
+            ExpKind::Seq(e1, e2) => ESeq(lower(e1), lower(e2)),
+            ExpKind::Alloc(e1, e2) => EAlloc(lower(e1), lower(e2)),
+            ExpKind::Set(e1, e2, e3) => ESet(lower(e1), lower(e2), lower(e3)),
+            ExpKind::Get(e1, e2) => EGet(lower(e1), lower(e2)),
+            ExpKind::Cond(e1, e2, e3) => ECond(lower(e1), lower(e2), lower(e3)),
+            ExpKind::Funptr(f) => EFunptr(f.clone()),
+            ExpKind::Call(f, args) => ECall(lower(f), args.iter().map(Exp::lower).collect()),
+            ExpKind::CallFun(f, args) => ECall(Box::new(EFunptr(f.clone())), args.iter().map(Exp::lower).collect()),
+            ExpKind::Print(e) => EPrint(lower(e)),
+            ExpKind::Spawn(e) => ESpawn(lower(e)),
+        }
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+
+use asm::Val;
+use asm::Val::*;
+use ir::*;
+use lexer::Span;
+use types::{eval_binop,Binop,OverflowMode,Unop};
+
+/********************************************
+ * A reference interpreter for GrumpyIR
+ ********************************************/
+
+/* A definitional interpreter for whole GrumpyIR programs, following
+   doc/ir.md, to check compiled code against. Values are GrumpyVM's
+   (asm::Val), laid out as the VM lays them out:
+
+   - An array of n values is n+1 heap cells, Vsize(n) followed by the
+     elements, and is referred to by Vaddr of its first cell.
+   - A function pointer, (funptr f), is Vloc(i) for the ith function
+     of the program, standing for the label of f's code.
+
+   Expressions are evaluated left to right, except that a call evaluates
+   its arguments before the function pointer, as in doc/codegen.md.
+   Arithmetic overflows as given by an OverflowMode (see types.rs).
+
+   The interpreter doesn't recurse on subexpressions, so deep recursion
+   in a program can't overflow the Rust stack. Instead it keeps a stack
+   of Frames, each the rest of the evaluation of an expression waiting
+   for the value of one of its subexpressions, and the program's own
+   recursion is bounded by MAX_FRAMES.
+
+   (spawn e) expects e to be the address of a closure: an array whose
+   first element is a pointer to a function of two arguments. The
+   function isn't called right away. Instead, once main has finished,
+   each spawned thread is run to completion in turn, on the closure and
+   tt, as if that were the schedule a VM had chosen. As in pa/3.md, a
+   thread gets a copy of its parent's heap as it was at the spawn, so
+   it doesn't see the writes its parent or other threads make after
+   that. The result of the program is main's. */
+
+pub const HEAP_SIZE: usize = 1 << 20;
+//The most frames allowed on the interpreter's stack
+pub const MAX_FRAMES: usize = 1 << 18;
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct RuntimeError {
+    pub msg: String,
+    pub span: Span,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}:{}", self.msg, self.span.start.line_no, self.span.start.col_no)
+    }
+}
+
+pub type Res<T> = Result<T, RuntimeError>;
+
+fn err<T>(msg: String, span: &Span) -> Res<T> {
+    Err(RuntimeError{msg, span: span.clone()})
+}
+
+//Local variables, innermost last
+type Env<'p> = Vec<(&'p str, Val)>;
+
+pub struct Interpreter<'p> {
+    prog: &'p Program,
+    funs: HashMap<&'p str, usize>,
+    pub mode: OverflowMode,
+    pub heap: Vec<Val>,
+    //The bytes printed so far
+    pub output: Vec<u8>,
+    //Threads spawned but not yet run, as the function, the closure and
+    //the heap they start with
+    spawned: Vec<(usize, Val, Vec<Val>, Span)>,
+}
+
+impl<'p> Interpreter<'p> {
+    pub fn new(prog: &'p Program) -> Self {
+        Interpreter::with_mode(prog, OverflowMode::Trap)
+    }
+
+    pub fn with_mode(prog: &'p Program, mode: OverflowMode) -> Self {
+        let mut funs = HashMap::new();
+        for (i, f) in prog.funs.iter().enumerate() {
+            funs.entry(f.name.as_str()).or_insert(i);
+        }
+        Interpreter{prog, funs, mode, heap: vec![], output: vec![], spawned: vec![]}
+    }
+
+    //Run the program: main, then any threads it spawned.
+    pub fn run(&mut self) -> Res<Val> {
+        let prog = self.prog;
+        let v = self.eval(&mut vec![], &prog.main)?;
+        let heap = self.heap.clone();
+        while !self.spawned.is_empty() {
+            let (f, closure, heap, span) = self.spawned.remove(0);
+            self.heap = heap;
+            let (mut env, body) = self.enter(f, vec![closure, Vunit], &span)?;
+            self.eval(&mut env, body)?;
+        }
+        //Leave main's heap
+        self.heap = heap;
+        Ok(v)
+    }
+
+    fn fun(&self, name: &str, span: &Span) -> Res<usize> {
+        match self.funs.get(name) {
+            Some(i) => Ok(*i),
+            None => err(format!("unknown function {}", name), span)
+        }
+    }
+
+    //The environment and body of a call of the fth function on args
+    fn enter(&self, f: usize, args: Vec<Val>, span: &Span) -> Res<(Env<'p>, &'p Exp)> {
+        let fun = &self.prog.funs[f];
+        if args.len() != fun.params.len() {
+            return err(format!("{} expects {} argument(s), found {}", fun.name, fun.params.len(), args.len()), span)
+        }
+        Ok((fun.params.iter().map(|p| p.name.as_str()).zip(args).collect(), &fun.body))
+    }
+
+    //Call the fth function, returning to the caller's environment after
+    fn call(&mut self, env: &mut Env<'p>, stack: &mut Vec<Frame<'p>>, f: usize, args: Vec<Val>, span: &Span) -> Res<Step<'p>> {
+        let (callee, body) = self.enter(f, args, span)?;
+        stack.push(Frame::Return(mem::replace(env, callee)));
+        Ok(Step::Eval(body))
+    }
+
+    //The array at v and the index idx in it, as a heap address
+    fn element(&self, arr: Val, idx: Val, span: &Span) -> Res<usize> {
+        let (base, i) = match (arr, idx) {
+            (Vaddr(base), Vi32(i)) => (base, i),
+            (Vaddr(_), v) => return err(format!("expected an i32 index, found {:?}", v), span),
+            (v, _) => return err(format!("expected an array, found {:?}", v), span)
+        };
+        match self.heap.get(base) {
+            Some(Vsize(size)) if 0 <= i && i < *size => Ok(base + i as usize + 1),
+            Some(Vsize(size)) => err(format!("index {} out of bounds for an array of size {}", i, size), span),
+            _ => err(format!("Vaddr({}) isn't the address of an array", base), span)
+        }
+    }
+
+    //Evaluate the next of the arguments rest of a call, or once they've
+    //all been evaluated, the call
+    fn args(&mut self, env: &mut Env<'p>, stack: &mut Vec<Frame<'p>>, done: Vec<Val>, rest: &'p [Exp],
+            callee: Callee<'p>, span: &'p Span) -> Res<Step<'p>> {
+        match rest.split_first() {
+            Some((e, rest)) => {
+                stack.push(Frame::Args(done, rest, callee, span));
+                Ok(Step::Eval(e))
+            },
+            None => match callee {
+                Callee::Ptr(f) => {
+                    stack.push(Frame::CallPtr(done, span));
+                    Ok(Step::Eval(f))
+                },
+                Callee::Named(f) => {
+                    let f = self.fun(f, span)?;
+                    self.call(env, stack, f, done, span)
+                }
+            }
+        }
+    }
+
+    pub fn eval(&mut self, env: &mut Env<'p>, e: &'p Exp) -> Res<Val> {
+        let mut stack = vec![];
+        let mut step = Step::Eval(e);
+        loop {
+            step = match step {
+                Step::Eval(e) => {
+                    if stack.len() == MAX_FRAMES { return err("stack overflow".to_string(), &e.span) }
+                    self.start(env, &mut stack, e)?
+                },
+                Step::Value(v) => match stack.pop() {
+                    Some(frame) => self.resume(env, &mut stack, frame, v)?,
+                    None => return Ok(v)
+                }
+            }
+        }
+    }
+
+    //Start evaluating e
+    fn start(&mut self, env: &mut Env<'p>, stack: &mut Vec<Frame<'p>>, e: &'p Exp) -> Res<Step<'p>> {
+        let span = &e.span;
+        let (frame, sub) = match &e.kind {
+            ExpKind::I32(i) => return Ok(Step::Value(Vi32(*i))),
+            ExpKind::Bool(b) => return Ok(Step::Value(Vbool(*b))),
+            ExpKind::Unit => return Ok(Step::Value(Vunit)),
+            ExpKind::Var(x) => return match env.iter().rev().find(|(y, _)| y == x) {
+                Some((_, v)) => Ok(Step::Value(v.clone())),
+                None => err(format!("unbound variable {}", x), span)
+            },
+            ExpKind::Funptr(f) => return Ok(Step::Value(Vloc(self.fun(f, span)? as u32))),
+            ExpKind::Call(f, args) => return self.args(env, stack, vec![], args, Callee::Ptr(f), span),
+            ExpKind::CallFun(f, args) => return self.args(env, stack, vec![], args, Callee::Named(f), span),
+            ExpKind::Unop(op, e) => (Frame::Unop(op, span), e),
+            ExpKind::Binop(op, e1, e2) => (Frame::Binop(op, e2, span), e1),
+            ExpKind::Let(x, e1, e2) => (Frame::Let(x, e2), e1),
+            ExpKind::Seq(e1, e2) => (Frame::Seq(e2), e1),
+            ExpKind::Alloc(e1, e2) => (Frame::Alloc(e2, span), e1),
+            ExpKind::Set(e1, e2, e3) => (Frame::Set(e2, e3, span), e1),
+            ExpKind::Get(e1, e2) => (Frame::Get(e2, span), e1),
+            ExpKind::Cond(e1, e2, e3) => (Frame::Cond(e2, e3, span), e1),
+            ExpKind::Print(e) => (Frame::Print(span), e),
+            ExpKind::Spawn(e) => (Frame::Spawn(span), e)
+        };
+        stack.push(frame);
+        Ok(Step::Eval(sub))
+    }
+
+    //Carry on with frame, given v, the value of the subexpression it
+    //was waiting for
+    fn resume(&mut self, env: &mut Env<'p>, stack: &mut Vec<Frame<'p>>, frame: Frame<'p>, v: Val) -> Res<Step<'p>> {
+        let v = match frame {
+            Frame::Unop(Unop::UNeg, span) => match v {
+                Vbool(b) => Vbool(!b),
+                v => return err(format!("neg expects a bool, found {:?}", v), span)
+            },
+            Frame::Unop(Unop::UMinus, span) => match v {
+                Vi32(i) => eval_binop(&Binop::BMinus, 0, i, self.mode).map(Vi32)
+                    .or_else(|e| err(e.to_string(), span))?,
+                v => return err(format!("- expects an i32, found {:?}", v), span)
+            },
+            Frame::Binop(op, e2, span) => return then(stack, Frame::Binop2(op, v, span), e2),
+            Frame::Binop2(op, v1, span) => {
+                let (i1, i2) = match (v1, v) {
+                    (Vi32(i1), Vi32(i2)) => (i1, i2),
+                    (v1, v2) => return err(format!("{} expects i32s, found {:?} and {:?}", op.to_string(), v1, v2), span)
+                };
+                let v = eval_binop(op, i1, i2, self.mode).or_else(|e| err(e.to_string(), span))?;
+                match op {
+                    Binop::BLt | Binop::BEq => Vbool(v != 0),
+                    _ => Vi32(v)
+                }
+            },
+            Frame::Let(x, e2) => {
+                env.push((x, v));
+                return then(stack, Frame::Unbind, e2)
+            },
+            Frame::Unbind => {
+                env.pop();
+                v
+            },
+            Frame::Seq(e2) => return Ok(Step::Eval(e2)),
+            Frame::Alloc(e2, span) => return then(stack, Frame::Alloc2(v, span), e2),
+            Frame::Alloc2(size, span) => {
+                let size = match size {
+                    Vi32(n) if n >= 0 => n,
+                    v => return err(format!("expected a nonnegative i32 array size, found {:?}", v), span)
+                };
+                if self.heap.len() + size as usize + 1 > HEAP_SIZE { return err("out of memory".to_string(), span) }
+                let base = self.heap.len();
+                self.heap.push(Vsize(size));
+                self.heap.extend((0..size).map(|_| v.clone()));
+                Vaddr(base)
+            },
+            Frame::Set(e2, e3, span) => return then(stack, Frame::Set2(v, e3, span), e2),
+            Frame::Set2(arr, e3, span) => return then(stack, Frame::Set3(arr, v, span), e3),
+            Frame::Set3(arr, idx, span) => {
+                let addr = self.element(arr, idx, span)?;
+                self.heap[addr] = v;
+                Vunit
+            },
+            Frame::Get(e2, span) => return then(stack, Frame::Get2(v, span), e2),
+            Frame::Get2(arr, span) => {
+                let addr = self.element(arr, v, span)?;
+                self.heap[addr].clone()
+            },
+            Frame::Cond(e2, e3, span) => return match v {
+                Vbool(true) => Ok(Step::Eval(e2)),
+                Vbool(false) => Ok(Step::Eval(e3)),
+                v => err(format!("cond expects a bool, found {:?}", v), span)
+            },
+            Frame::Args(mut done, rest, callee, span) => {
+                done.push(v);
+                return self.args(env, stack, done, rest, callee, span)
+            },
+            Frame::CallPtr(args, span) => return match v {
+                Vloc(i) if (i as usize) < self.prog.funs.len() => self.call(env, stack, i as usize, args, span),
+                v => err(format!("expected a function pointer, found {:?}", v), span)
+            },
+            Frame::Return(caller) => {
+                *env = caller;
+                v
+            },
+            Frame::Print(span) => match v {
+                Vi32(i) => {
+                    self.output.push(i as u8);
+                    Vunit
+                },
+                v => return err(format!("print expects an i32, found {:?}", v), span)
+            },
+            Frame::Spawn(span) => {
+                let addr = self.element(v.clone(), Vi32(0), span)?;
+                match self.heap[addr] {
+                    Vloc(f) if (f as usize) < self.prog.funs.len() => {
+                        self.spawned.push((f as usize, v, self.heap.clone(), span.clone()));
+                        Vunit
+                    },
+                    ref v => return err(format!("expected a closure's function pointer, found {:?}", v), span)
+                }
+            }
+        };
+        Ok(Step::Value(v))
+    }
+}
+
+//Push frame, then evaluate e
+fn then<'p>(stack: &mut Vec<Frame<'p>>, frame: Frame<'p>, e: &'p Exp) -> Res<Step<'p>> {
+    stack.push(frame);
+    Ok(Step::Eval(e))
+}
+
+//What's called once a call's arguments have been evaluated: the
+//function pointer an expression evaluates to, or a named function
+enum Callee<'p> {
+    Ptr(&'p Exp),
+    Named(&'p str),
+}
+
+//The rest of the evaluation of an expression, waiting for the value of
+//a subexpression. Frame::Op2 waits for the second operand of Op, and
+//so on.
+enum Frame<'p> {
+    Unop(&'p Unop, &'p Span),
+    Binop(&'p Binop, &'p Exp, &'p Span),
+    Binop2(&'p Binop, Val, &'p Span),
+    Let(&'p str, &'p Exp),
+    //The end of the scope of a let's variable
+    Unbind,
+    Seq(&'p Exp),
+    Alloc(&'p Exp, &'p Span),
+    Alloc2(Val, &'p Span),
+    Set(&'p Exp, &'p Exp, &'p Span),
+    Set2(Val, &'p Exp, &'p Span),
+    Set3(Val, Val, &'p Span),
+    Get(&'p Exp, &'p Span),
+    Get2(Val, &'p Span),
+    Cond(&'p Exp, &'p Exp, &'p Span),
+    //The arguments of a call evaluated so far, and those still to go
+    Args(Vec<Val>, &'p [Exp], Callee<'p>, &'p Span),
+    //Waiting for the function pointer, given the arguments
+    CallPtr(Vec<Val>, &'p Span),
+    //The end of a call, with the caller's environment
+    Return(Env<'p>),
+    Print(&'p Span),
+    Spawn(&'p Span),
+}
+
+//What to do next: evaluate an expression, or return a value to the
+//frame on top of the stack
+enum Step<'p> {
+    Eval(&'p Exp),
+    Value(Val),
+}
+
+//Run prog, returning its result and what it printed.
+pub fn run(prog: &Program) -> Res<(Val, String)> {
+    let mut interp = Interpreter::new(prog);
+    let v = interp.run()?;
+    Ok((v, String::from_utf8_lossy(&interp.output).into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ir_parser::parse_program;
+
+    fn run_str(s: &str) -> Res<(Val, String)> {
+        run(&parse_program(s).unwrap())
+    }
+
+    fn result(s: &str) -> Val {
+        run_str(s).unwrap().0
+    }
+
+    fn error(s: &str) -> String {
+        run_str(s).unwrap_err().msg
+    }
+
+    #[test]
+    fn doc_examples() {
+        assert_eq!(result("(fun f (x (array i32)) -> i32 \n  (seq (set x 23 42) \n       (get x 23)))\n%\n(f (alloc 100 7))"),
+                   Vi32(42));
+        assert_eq!(result("(fun fib (x i32) -> i32\n (cond (== x 0) 1\n (cond (== x 1) 1\n \
+                           (+ (fib (- x 1)) (fib (- x 2))))))\n%\n(fib 20)"),
+                   Vi32(10946));
+    }
+
+    #[test]
+    fn values_and_effects() {
+        assert_eq!(result("% (- 1 4)"), Vi32(-3));
+        assert_eq!(result("% (let x 3 (let x (< x 4) (neg x)))"), Vbool(false));
+        assert_eq!(result("% (let a (alloc 2 tt) (get a 1))"), Vunit);
+        assert_eq!(result("% (alloc 0 0)"), Vaddr(0));
+        assert_eq!(result("(fun f -> i32 1) (fun g -> i32 2) % (funptr g)"), Vloc(1));
+        assert_eq!(result("(fun twice (f i32) (x i32) -> i32 (call f (call f x)))\n\
+                           (fun inc (x i32) -> i32 (+ x 1))\n% (twice (funptr inc) 5)"), Vi32(7));
+        assert_eq!(run_str("% (seq (print 72) (seq (print 105) tt))"), Ok((Vunit, "Hi".to_string())));
+        //Spawned threads run after main
+        assert_eq!(run_str("(fun t (c (array i32)) (u unit) -> unit (print (get c 1)))\n\
+                            % (let c (alloc 2 (funptr t)) (seq (set c 1 66) (seq (spawn c) (print 65))))"),
+                   Ok((Vunit, "AB".to_string())));
+        //Each gets a copy of its parent's heap, which later writes don't
+        //change
+        assert_eq!(run_str("(fun t (c (array i32)) (u unit) -> unit (seq (print (get c 1)) (set c 1 90)))\n\
+                            % (let c (alloc 2 (funptr t)) (seq (set c 1 66) (seq (spawn c) (seq (spawn c)\n\
+                              (seq (set c 1 67) (seq (print (get c 1)) (get c 1)))))))"),
+                   Ok((Vi32(67), "CBB".to_string())));
+    }
+
+    //Neither deep recursion nor deep nesting overflows the Rust stack
+    #[test]
+    fn deep_evaluation() {
+        assert_eq!(result("(fun f (n i32) -> i32 (cond (== n 0) 0 (+ 1 (f (- n 1))))) % (f 100000)"), Vi32(100000));
+        //Built directly, since the parser recurses
+        let mut prog = parse_program("% 0").unwrap();
+        for _ in 0..10_000 {
+            let one = Exp{kind: ExpKind::I32(1), span: prog.main.span.clone()};
+            let span = prog.main.span.clone();
+            prog.main = Exp{kind: ExpKind::Binop(Binop::BPlus, Box::new(one), Box::new(prog.main)), span};
+        }
+        assert_eq!(run(&prog).map(|(v, _)| v), Ok(Vi32(10_000)));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("% (get (alloc 3 0) 3)"), "index 3 out of bounds for an array of size 3");
+        assert_eq!(error("% (set (alloc 3 0) -1 0)"), "index -1 out of bounds for an array of size 3");
+        assert_eq!(error("% (alloc -1 0)"), "expected a nonnegative i32 array size, found Vi32(-1)");
+        assert_eq!(error("% (+ 1 true)"), "+ expects i32s, found Vi32(1) and Vbool(true)");
+        assert_eq!(error("% (/ 1 0)"), "division by zero");
+        assert_eq!(error("% (+ 2147483647 1)"), "arithmetic overflow in +");
+        assert_eq!(error("% (cond 1 2 3)"), "cond expects a bool, found Vi32(1)");
+        assert_eq!(error("% (let x 1 y)"), "unbound variable y");
+        assert_eq!(error("% (f 1)"), "unknown function f");
+        assert_eq!(error("(fun f (x i32) -> i32 x) % (f)"), "f expects 1 argument(s), found 0");
+        assert_eq!(error("(fun f -> i32 (f)) % (f)"), "stack overflow");
+        assert_eq!(error("% (call 3)"), "expected a function pointer, found Vi32(3)");
+        let err = run_str("%\n(seq tt\n  (get tt 0))").unwrap_err();
+        assert_eq!((err.span.start.line_no, err.span.start.col_no), (3, 2));
+        let prog = parse_program("% (* 65536 65536)").unwrap();
+        assert_eq!(Interpreter::with_mode(&prog, OverflowMode::Wrap).run(), Ok(Vi32(0)));
+    }
+}
@@ -0,0 +1,312 @@
+use std::io::BufRead;
+
+use ir::*;
+use lexer::{LexError,LexerState,Span,Tok,TokenStream};
+use lexer::Tok::*;
+use parser::ParseError;
+use stream_lexer::StreamLexer;
+use types::{Binop,Ty,Unop};
+use types::Binop::*;
+use types::Ty::*;
+
+/* GRAMMAR (see doc/ir.md):
+
+   <prog> ::= <fun>* % <exp>
+    <fun> ::= ( fun <id> <param>* -> <ty> <exp> )
+  <param> ::= ( <id> <ty> )
+     <ty> ::= i32 | bool | unit | ( array <ty> )
+    <exp> ::= <i32> | true | false | tt | <id>
+            | ( neg <exp> )
+            | ( <binop> <exp> <exp> )
+            | ( let <id> <exp> <exp> )
+            | ( seq <exp> <exp> )
+            | ( alloc <exp> <exp> )
+            | ( set <exp> <exp> <exp> )
+            | ( get <exp> <exp> )
+            | ( cond <exp> <exp> <exp> )
+            | ( funptr <id> )
+            | ( call <exp> <exp>* )
+            | ( <id> <exp>* )
+            | ( print <exp> )
+            | ( spawn <exp> )
+
+   Every alternative of each nonterminal starts with a different token,
+   except that a ( starting an <exp> is followed by a different token
+   in each, so the parser needs just one token of lookahead. */
+
+pub type Res<T> = Result<T, ParseError>;
+
+pub fn err<T>(msg: String, span: Span) -> Res<T> {
+    Err(ParseError{msg, span})
+}
+
+//The span from the start of first to the end of last
+pub fn span_from(first: &Span, last: &Span) -> Span {
+    Span{start: first.start.clone(), end: last.end.clone()}
+}
+
+//Consume expected, returning its span.
+pub fn eat<L: TokenStream>(l: &mut L, expected: Tok) -> Res<Span> {
+    let (tok, span) = l.next_spanned()?;
+    if tok == expected { Ok(span) }
+    else { err(format!("expected {:?}, found {:?}", expected, tok), span) }
+}
+
+pub fn parse_id<L: TokenStream>(l: &mut L) -> Res<String> {
+    match l.next_spanned()? {
+        (ID(x), _) => Ok(x),
+        (tok, span) => err(format!("expected an identifier, found {:?}", tok), span)
+    }
+}
+
+fn parse_ty<L: TokenStream>(l: &mut L) -> Res<Ty> {
+    match l.next_spanned()? {
+        (I32TY, _) => Ok(TyI32),
+        (BOOLTY, _) => Ok(TyBool),
+        (UNITTY, _) => Ok(TyUnit),
+        (LPAREN, _) => {
+            eat(l, ARRAYTY)?;
+            let t = parse_ty(l)?;
+            eat(l, RPAREN)?;
+            Ok(TyArray(Box::new(t)))
+        },
+        (tok, span) => err(format!("expected a type, found {:?}", tok), span)
+    }
+}
+
+pub fn binop_of(tok: &Tok) -> Option<Binop> {
+    match tok {
+        PLUS => Some(BPlus),
+        TIMES => Some(BTimes),
+        MINUS => Some(BMinus),
+        DIV => Some(BDiv),
+        LT => Some(BLt),
+        EQ => Some(BEq),
+        _ => None
+    }
+}
+
+fn parse_exp<L: TokenStream>(l: &mut L) -> Res<Exp> {
+    let (tok, span) = l.next_spanned()?;
+    let kind = match tok {
+        I32(i) => ExpKind::I32(i),
+        TRUE => ExpKind::Bool(true),
+        FALSE => ExpKind::Bool(false),
+        TT => ExpKind::Unit,
+        ID(x) => ExpKind::Var(x),
+        LPAREN => return parse_form(l, span),
+        tok => return err(format!("expected an expression, found {:?}", tok), span)
+    };
+    Ok(Exp{kind, span})
+}
+
+fn parse_box<L: TokenStream>(l: &mut L) -> Res<Box<Exp>> {
+    parse_exp(l).map(Box::new)
+}
+
+//Parse expressions up to the next )
+fn parse_args<L: TokenStream>(l: &mut L) -> Res<Vec<Exp>> {
+    let mut args = vec![];
+    while l.peek()? != RPAREN { args.push(parse_exp(l)?) }
+    Ok(args)
+}
+
+//Parse the rest of an expression starting with (, whose span is open
+fn parse_form<L: TokenStream>(l: &mut L, open: Span) -> Res<Exp> {
+    let (tok, span) = l.next_spanned()?;
+    let kind =
+        if let Some(op) = binop_of(&tok) {
+            let e1 = parse_box(l)?;
+            ExpKind::Binop(op, e1, parse_box(l)?)
+        } else {
+            match tok {
+                NEG => ExpKind::Unop(Unop::UNeg, parse_box(l)?),
+                LET => {
+                    let x = parse_id(l)?;
+                    let e1 = parse_box(l)?;
+                    ExpKind::Let(x, e1, parse_box(l)?)
+                },
+                SEQ => {
+                    let e1 = parse_box(l)?;
+                    ExpKind::Seq(e1, parse_box(l)?)
+                },
+                ALLOC => {
+                    let esize = parse_box(l)?;
+                    ExpKind::Alloc(esize, parse_box(l)?)
+                },
+                SET => {
+                    let earr = parse_box(l)?;
+                    let eidx = parse_box(l)?;
+                    ExpKind::Set(earr, eidx, parse_box(l)?)
+                },
+                GET => {
+                    let earr = parse_box(l)?;
+                    ExpKind::Get(earr, parse_box(l)?)
+                },
+                COND => {
+                    let econd = parse_box(l)?;
+                    let e1 = parse_box(l)?;
+                    ExpKind::Cond(econd, e1, parse_box(l)?)
+                },
+                FUNPTR => ExpKind::Funptr(parse_id(l)?),
+                CALL => {
+                    let e = parse_box(l)?;
+                    ExpKind::Call(e, parse_args(l)?)
+                },
+                ID(f) => ExpKind::CallFun(f, parse_args(l)?),
+                PRINT => ExpKind::Print(parse_box(l)?),
+                SPAWN => ExpKind::Spawn(parse_box(l)?),
+                tok => return err(format!("expected an operator, keyword or function name, found {:?}", tok), span)
+            }
+        };
+    let close = eat(l, RPAREN)?;
+    Ok(Exp{kind, span: span_from(&open, &close)})
+}
+
+fn parse_fun<L: TokenStream>(l: &mut L) -> Res<FunDef> {
+    let open = eat(l, LPAREN)?;
+    eat(l, FUN)?;
+    let name = parse_id(l)?;
+    let mut params = vec![];
+    while l.peek()? == LPAREN {
+        let start = eat(l, LPAREN)?;
+        let x = parse_id(l)?;
+        let ty = parse_ty(l)?;
+        let end = eat(l, RPAREN)?;
+        params.push(Param{name: x, ty, span: span_from(&start, &end)})
+    }
+    eat(l, ARROW)?;
+    let ret_ty = parse_ty(l)?;
+    let body = parse_exp(l)?;
+    let close = eat(l, RPAREN)?;
+    Ok(FunDef{name, params, ret_ty, body, span: span_from(&open, &close)})
+}
+
+//Parse a whole program from l, which must contain nothing after it.
+pub fn parse_program_from<L: TokenStream>(l: &mut L) -> Res<Program> {
+    let mut funs = vec![];
+    while l.peek()? == LPAREN { funs.push(parse_fun(l)?) }
+    eat(l, PERCENT)?;
+    let main = parse_exp(l)?;
+    match l.peek_spanned() {
+        Err(LexError::UnexpectedEof(_)) => Ok(Program{funs, main}),
+        Ok((tok, span)) => err(format!("expected end of program, found {:?}", tok), span),
+        Err(e) => Err(e.into())
+    }
+}
+
+pub fn parse_program(s: &str) -> Res<Program> {
+    parse_program_from(&mut LexerState::new(s))
+}
+
+//Parse a program read incrementally from r.
+pub fn parse_program_reader<R: BufRead>(r: R) -> Res<Program> {
+    parse_program_from(&mut StreamLexer::new(r))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::LineInfo;
+    use source_map::SourceMap;
+
+    //The position of the byte at offset in s
+    fn at(s: &str, offset: usize) -> LineInfo {
+        SourceMap::new(s).info_at(offset as u64)
+    }
+
+    #[test]
+    fn doc_examples() {
+        let ex1 = "(fun f (x (array i32)) -> i32 \n  (seq (set x 23 42) \n       (get x 23)))\n%\n(f (alloc 100 7))";
+        let p = parse_program(ex1).unwrap();
+        assert_eq!(p.funs.len(), 1);
+        let f = &p.funs[0];
+        assert_eq!(f.name, "f");
+        assert_eq!(f.params[0].name, "x");
+        assert_eq!(f.params[0].ty, TyArray(Box::new(TyI32)));
+        assert_eq!(f.ret_ty, TyI32);
+        assert!(matches!(f.body.kind, ExpKind::Seq(..)));
+        match p.main.kind {
+            ExpKind::CallFun(ref g, ref args) => {
+                assert_eq!(g, "f");
+                assert!(matches!(args[..], [Exp{kind: ExpKind::Alloc(..), ..}]));
+            },
+            ref kind => panic!("unexpected main {:?}", kind)
+        }
+
+        let ex2 = "(fun fib (x i32) -> i32\n     (cond (== x 0) 1\n           (cond (== x 1) 1\n\t   \t \
+                   (+ (fib (- x 1)) (fib (- x 2))))))\t\t \n%\n(fib 20)";
+        let p = parse_program(ex2).unwrap();
+        assert_eq!(p.funs[0].name, "fib");
+        assert_eq!(parse_program_reader(ex2.as_bytes()), Ok(p));
+    }
+
+    #[test]
+    fn every_form() {
+        let s = "(fun g -> unit tt) (fun h (a bool) (b unit) -> (array (array bool)) (alloc 1 (alloc 2 a)))\n%\n\
+                 (let x (neg true) (seq (print 65) (cond x (get (alloc 3 -1) 0) \
+                 (call (funptr g) (spawn (funptr g)) false (/ (* 1 2) (< 3 (== 4 5)))))))";
+        let p = parse_program(s).unwrap();
+        assert_eq!(p.funs[0].params, vec![]);
+        assert_eq!(p.funs[1].params.len(), 2);
+        assert_eq!(p.funs[1].ret_ty, TyArray(Box::new(TyArray(Box::new(TyBool)))));
+        let let_exp = &p.main;
+        assert_eq!(let_exp.span, Span{start: at(s, s.find("(let").unwrap()), end: at(s, s.len())});
+        let body = match let_exp.kind {
+            ExpKind::Let(ref x, ref e1, ref body) => {
+                assert_eq!(x, "x");
+                assert_eq!(e1.kind, ExpKind::Unop(Unop::UNeg, Box::new(Exp{
+                    kind: ExpKind::Bool(true),
+                    span: Span{start: at(s, s.find("true").unwrap()), end: at(s, s.find("true").unwrap() + 4)}})));
+                body
+            },
+            ref kind => panic!("unexpected {:?}", kind)
+        };
+        let cond = match body.kind {
+            ExpKind::Seq(ref e1, ref e2) => {
+                assert!(matches!(e1.kind, ExpKind::Print(_)));
+                e2
+            },
+            ref kind => panic!("unexpected {:?}", kind)
+        };
+        match cond.kind {
+            ExpKind::Cond(_, ref e1, ref e2) => {
+                assert!(matches!(e1.kind, ExpKind::Get(..)));
+                match e2.kind {
+                    ExpKind::Call(ref f, ref args) => {
+                        assert_eq!(f.kind, ExpKind::Funptr("g".to_string()));
+                        assert_eq!(args.len(), 3);
+                        assert!(matches!(args[0].kind, ExpKind::Spawn(_)));
+                        assert!(matches!(args[2].kind, ExpKind::Binop(BDiv, ..)));
+                    },
+                    ref kind => panic!("unexpected {:?}", kind)
+                }
+            },
+            ref kind => panic!("unexpected {:?}", kind)
+        }
+        assert!(parse_program("% (set (alloc 1 tt) 0 tt)").is_ok());
+    }
+
+    #[test]
+    fn lowers_to_canonical_form() {
+        let s = "(let x (neg true) (seq (print 65) (cond x (get (alloc 3 -1) 0) \
+                 (call (funptr g) (spawn (funptr g)) false (/ (* 1 2) (< 3 (== 4 5)))))))";
+        let e = parse_program(&format!("% {}", s)).unwrap().main.lower();
+        assert_eq!(e.to_string(), s.split_whitespace().collect::<Vec<_>>().join(" "));
+        let e = parse_program("%\n(let a (alloc 1 tt)\n  (f a (set a 0 tt) y))").unwrap().main.lower();
+        assert_eq!(e.to_string(), "(let a (alloc 1 tt) (call (funptr f) a (set a 0 tt) y))");
+    }
+
+    #[test]
+    fn errors() {
+        let msg = |s: &str| parse_program(s).unwrap_err().msg;
+        assert_eq!(msg("(fun f -> i32 1) (+ 1 2)"), "expected FUN, found PLUS");
+        assert_eq!(msg("% (+ 1 2) 3"), "expected end of program, found I32(3)");
+        assert_eq!(msg("% (1 2)"), "expected an operator, keyword or function name, found I32(1)");
+        assert_eq!(msg("(fun f (x int) -> i32 x) % 1"), "expected a type, found ID(\"int\")");
+        assert_eq!(msg("% (let 1 2 3)"), "expected an identifier, found I32(1)");
+        assert_eq!(msg("% (+ 1"), "unexpected end of program");
+        let err = parse_program("%\n(get x)").unwrap_err();
+        assert_eq!(err.span.start, at("%\n(get x)", 8));
+    }
+}
@@ -1,4 +1,6 @@
-use regex::Regex;
+use std::fmt;
+use std::io;
+use std::str;
 
 #[derive(Debug,Clone,PartialEq)]
 pub enum Tok {
@@ -6,164 +8,1126 @@ pub enum Tok {
     TIMES,
     I32(i32),
     DOLLAR,
+
+    //GrumpyIR (doc/ir.md)
+    LPAREN,
+    RPAREN,
+    ID(String),
+    TRUE,
+    FALSE,
+    TT,
+    MINUS,
+    DIV,
+    LT,
+    EQ,
+    ARROW,
+    PERCENT,
+
+    //Keywords
+    NEG,
+    LET,
+    SEQ,
+    ALLOC,
+    SET,
+    GET,
+    COND,
+    FUNPTR,
+    CALL,
+    FUN,
+    PRINT,
+    SPAWN,
+
+    //Type names
+    I32TY,
+    BOOLTY,
+    UNITTY,
+    ARRAYTY,
 }
 
-#[derive(Debug,Clone)]
+//The token for a reserved word, if s is one.
+fn keyword(s: &str) -> Option<Tok> {
+    match s {
+        "true" => Some(Tok::TRUE),
+        "false" => Some(Tok::FALSE),
+        "tt" => Some(Tok::TT),
+        "neg" => Some(Tok::NEG),
+        "let" => Some(Tok::LET),
+        "seq" => Some(Tok::SEQ),
+        "alloc" => Some(Tok::ALLOC),
+        "set" => Some(Tok::SET),
+        "get" => Some(Tok::GET),
+        "cond" => Some(Tok::COND),
+        "funptr" => Some(Tok::FUNPTR),
+        "call" => Some(Tok::CALL),
+        "fun" => Some(Tok::FUN),
+        "print" => Some(Tok::PRINT),
+        "spawn" => Some(Tok::SPAWN),
+        "i32" => Some(Tok::I32TY),
+        "bool" => Some(Tok::BOOLTY),
+        "unit" => Some(Tok::UNITTY),
+        "array" => Some(Tok::ARRAYTY),
+        _ => None
+    }
+}
+
+/* Positions are tracked three ways: col_no counts characters since the
+   start of the line, display_col counts the columns those characters
+   occupy in a terminal (tabs stop every TAB_WIDTH columns, East Asian
+   wide characters take two, combining marks none), and byte_offset
+   counts bytes from the start of the input. */
+#[derive(Debug,Clone,PartialEq)]
 pub struct LineInfo {
     pub line_no: u64,
-    pub col_no: u64
+    pub col_no: u64,
+    pub display_col: u64,
+    pub byte_offset: u64,
+}
+
+pub const TAB_WIDTH: u64 = 8;
+
+//The number of terminal columns c occupies. An approximation of
+//Unicode's East Asian Width property, covering the common ranges.
+pub fn display_width(c: char) -> u64 {
+    match c as u32 {
+        0x0300..=0x036F | 0x1AB0..=0x1AFF | 0x1DC0..=0x1DFF | 0x200B..=0x200F
+            | 0x20D0..=0x20FF | 0xFE00..=0xFE0F | 0xFE20..=0xFE2F => 0,
+        0x1100..=0x115F | 0x2E80..=0x303E | 0x3041..=0x33FF | 0x3400..=0x4DBF
+            | 0x4E00..=0x9FFF | 0xA000..=0xA4CF | 0xAC00..=0xD7A3 | 0xF900..=0xFAFF
+            | 0xFE30..=0xFE4F | 0xFF00..=0xFF60 | 0xFFE0..=0xFFE6 | 0x1F300..=0x1F64F
+            | 0x1F900..=0x1F9FF | 0x20000..=0x3FFFD => 2,
+        _ => 1
+    }
 }
 
 impl LineInfo {
-    fn incr_line(&mut self, n: u64) {
+    //The position at the start of the input
+    pub fn start() -> LineInfo {
+        LineInfo{line_no: 1, col_no: 0, display_col: 0, byte_offset: 0}
+    }
+
+    //Advance past a line break of the given length in bytes.
+    fn incr_line(&mut self, bytes: u64) {
         self.col_no = 0;
-        self.line_no = self.line_no + n
-    }
-
-    fn incr_col(&mut self, n: u64) {
-        self.col_no = self.col_no + n
-    }
-}
-
-macro_rules! lex_upd {
-    ( $l:expr, $no_chars:expr, $tok:expr ) => {{
-        $l.info.incr_col($no_chars);
-        $l.rest = $l.rest.split_at($no_chars).1;
-        if $l.comment_depth > 0 { lex($l) }
-        else { Ok($tok) }
-    }}
-}
-
-//returns (rest, tok, tok_num_chars)
-fn lex<'a>(l: &mut LexerState<'a>) -> Result<Tok, String> {
-    let s = l.rest;
-
-    //Comments
-    if s.starts_with("/*") { 
-        l.comment_depth = l.comment_depth + 1;
-        l.rest = s.split_at(2).1;
-        lex(l)
-    }                
-    else if s.starts_with("*/") {
-        l.comment_depth = l.comment_depth - 1;
-        l.rest = s.split_at(2).1;
-        lex(l)
-    }
-    
-    //Whitespace characters
-    else if s.starts_with(" ") {
-        l.info.incr_col(1);
-        l.rest = s.split_at(1).1;
-        lex(l)
-    }
-
-    else if s.starts_with("\t") {
-        l.info.incr_col(1);
-        l.rest = s.split_at(1).1;
-        lex(l)
-    }        
-
-    //Newline character sequences
-    else if s.starts_with("\r\n") {
-        l.info.incr_line(1);
-        l.rest = s.split_at(2).1;
-        lex(l)
-    }    
-    else if s.starts_with("\r") {
-        l.info.incr_line(1);
-        l.rest = s.split_at(1).1;
-        lex(l)
-    }
-    else if s.starts_with("\n") {
-        l.info.incr_line(1);
-        l.rest = s.split_at(1).1;
-        lex(l)
-    }
-
-    //The rest
-    else if s.starts_with("+") { lex_upd!(l, 1, Tok::PLUS) }
-    else if s.starts_with("*") { lex_upd!(l, 1, Tok::TIMES) }
-    else if s.starts_with("$") { lex_upd!(l, 1, Tok::DOLLAR) }    
-    else {
-        match Regex::new(r"^\A[[:digit:]]+").unwrap().find(s) {
-            Some(mat) => {
-                assert_eq!(mat.start(), 0);
-                let (n, rest) = s.split_at(mat.end());
-                l.info.incr_col(mat.end() as u64);
-                l.rest = rest;
-                if l.comment_depth > 0 { lex(l) }
-                else { Ok(Tok::I32(n.parse::<i32>().unwrap())) }
+        self.display_col = 0;
+        self.line_no += 1;
+        self.byte_offset += bytes
+    }
+
+    //Advance past c, on the current line.
+    pub fn incr_char(&mut self, c: char) {
+        self.col_no += 1;
+        self.display_col =
+            if c == '\t' { (self.display_col / TAB_WIDTH + 1) * TAB_WIDTH }
+            else { self.display_col + display_width(c) };
+        self.byte_offset += c.len_utf8() as u64
+    }
+
+    //Advance past text, which must not contain line breaks. Invalid
+    //UTF-8 counts one column per replaced sequence.
+    fn incr_text(&mut self, text: &[u8]) {
+        let byte_offset = self.byte_offset + text.len() as u64;
+        for c in String::from_utf8_lossy(text).chars() { self.incr_char(c) }
+        self.byte_offset = byte_offset
+    }
+
+    //The position just past text, which must not contain line breaks.
+    pub fn advanced(&self, text: &str) -> LineInfo {
+        let mut info = self.clone();
+        info.incr_text(text.as_bytes());
+        info
+    }
+}
+
+//The source region between two positions
+#[derive(Debug,Clone,PartialEq)]
+pub struct Span {
+    pub start: LineInfo,
+    pub end: LineInfo
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum LexError {
+    UnexpectedChar(char, Span),
+    UnexpectedEof(Span),
+    UnterminatedComment(Span),
+    UnbalancedCommentClose(Span),
+    IntegerOverflow(String, Span),
+    //A literal run into characters it can't contain, like 12ab, or a
+    //bare 0x or 0b
+    MalformedInteger(String, Span),
+    //The input couldn't be read
+    ReadError(String, Span),
+    //A word that isn't in the token vocabulary being lexed
+    UnknownWord(String, Span),
+    //Raised by eat when the next token isn't the one expected
+    UnexpectedToken { expected: Tok, found: Tok, span: Span },
+}
+
+use lexer::LexError::*;
+
+impl LexError {
+    pub fn span(&self) -> &Span {
+        match self {
+            UnexpectedChar(_, span) => span,
+            UnexpectedEof(span) => span,
+            UnterminatedComment(span) => span,
+            UnbalancedCommentClose(span) => span,
+            IntegerOverflow(_, span) => span,
+            MalformedInteger(_, span) => span,
+            ReadError(_, span) => span,
+            UnknownWord(_, span) => span,
+            UnexpectedToken { span, .. } => span,
+        }
+    }
+
+    //The error message, without its position
+    pub fn message(&self) -> String {
+        match self {
+            UnexpectedChar(c, _) => format!("unexpected character '{}'", c),
+            UnexpectedEof(_) => "unexpected end of program".to_string(),
+            UnterminatedComment(_) => "unterminated comment".to_string(),
+            UnbalancedCommentClose(_) => "'*/' outside of a comment".to_string(),
+            IntegerOverflow(n, _) => format!("integer literal {} out of range", n),
+            MalformedInteger(n, _) => format!("malformed integer literal {}", n),
+            ReadError(err, _) => format!("couldn't read input: {}", err),
+            UnknownWord(w, _) => format!("unknown word '{}'", w),
+            UnexpectedToken { expected, found, .. } => format!("expected {:?}, found {:?}", expected, found),
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let start = &self.span().start;
+        write!(f, "{} at {}:{}", self.message(), start.line_no, start.col_no)
+    }
+}
+
+//Lets parsers that report String errors use ? on lexer results.
+impl From<LexError> for String {
+    fn from(err: LexError) -> String {
+        format!("lexer error: {}", err)
+    }
+}
+
+/********************************************
+ * Table-driven scanner
+ ********************************************/
+
+/* The scanner is a DFA over bytes. Each byte is first mapped to a
+   character class (CLASSES), then the DFA steps through DELTA until
+   it reaches DEAD. The longest prefix that ended in an accepting
+   state determines the lexeme (maximal munch). Both tables are built
+   at compile time, so no per-token setup is done at runtime. */
+
+/* Integer literals are decimal, hexadecimal (0x1F) or binary (0b101),
+   optionally preceded by a '-'. A '-' is part of a literal only when
+   it is immediately followed by a digit; a '-' followed by anything
+   else (e.g., whitespace) is left for a minus operator. So in
+   (- 3 -4), the first '-' is an operator and -4 is a literal. A
+   literal's value, including its sign, must fit in an i32: 0x7FFFFFFF
+   and -0x80000000 lex, 0xFFFFFFFF is an IntegerOverflow. A literal
+   mustn't run straight into a letter, digit or _ it can't contain:
+   12ab, 0b2 and 0x1g, like a bare 0x or 0b, are a MalformedInteger
+   spanning the whole run, rather than a literal and an identifier.
+
+   That's the rule for GrumpyIR and assembly, where operators come
+   first. For infix expressions (parser.rs), the lexer is made with
+   LexerState::infix, and there a '-' is a sign only where an operand
+   could start: at the start of the input, or after a token that can't
+   end an operand (see Vocab::ends_operand), like ( or an operator.
+   Otherwise it's a minus operator, so 1-1, 1 -1 and (2)-3 are all
+   subtractions, while in 1 - -1 and (-1) the -1 is a literal.
+
+   Identifiers are [a-zA-Z_][a-zA-Z0-9_]*. An identifier that spells a
+   keyword or type name (see keyword) lexes as that token instead. An
+   identifier immediately followed by ':' is a label definition, which
+   only the assembly vocabulary (asm_lexer.rs) accepts.
+
+   Line comments run from // to the end of the line. Inside a block
+   comment, // is ignored so that it can't hide the comment's end. */
+
+//Character classes
+const C_OTHER: usize = 0;
+const C_ZERO: usize = 1;
+const C_ONE: usize = 2;
+const C_DIGIT: usize = 3;  //'2'..'9'
+const C_B: usize = 4;      //'b', also a hex digit
+const C_X: usize = 5;
+const C_HEX: usize = 6;    //Hex digits [a-fA-F] other than 'b'
+const C_MINUS: usize = 7;
+const C_PLUS: usize = 8;
+const C_STAR: usize = 9;
+const C_SLASH: usize = 10;
+const C_DOLLAR: usize = 11;
+const C_BLANK: usize = 12; //' ' and '\t'
+const C_CR: usize = 13;
+const C_LF: usize = 14;
+const C_ALPHA: usize = 15; //Letters not covered above
+const C_UNDER: usize = 16;
+const C_LPAREN: usize = 17;
+const C_RPAREN: usize = 18;
+const C_LT: usize = 19;
+const C_EQ: usize = 20;
+const C_GT: usize = 21;
+const C_PERCENT: usize = 22;
+const C_COLON: usize = 23;
+const NUM_CLASSES: usize = 24;
+
+//DFA states
+const DEAD: u8 = 0;
+const START: u8 = 1;
+const S_ZERO: u8 = 2;     //0, possibly the start of 0x or 0b
+const S_DEC: u8 = 3;
+const S_HEX0: u8 = 4;     //0x
+const S_HEX: u8 = 5;
+const S_BIN0: u8 = 6;     //0b
+const S_BIN: u8 = 7;
+const S_MINUS: u8 = 8;
+const S_PLUS: u8 = 9;
+const S_STAR: u8 = 10;
+const S_DOLLAR: u8 = 11;
+const S_SLASH: u8 = 12;
+const S_OPEN: u8 = 13;    // /*
+const S_CLOSE: u8 = 14;   // */
+const S_LINE: u8 = 15;    // //...
+const S_BLANK: u8 = 16;
+const S_CR: u8 = 17;
+const S_CRLF: u8 = 18;
+const S_LF: u8 = 19;
+const S_ID: u8 = 20;
+const S_LPAREN: u8 = 21;
+const S_RPAREN: u8 = 22;
+const S_LT: u8 = 23;
+const S_EQ1: u8 = 24;     //=
+const S_EQ: u8 = 25;      //==
+const S_ARROW: u8 = 26;
+const S_PERCENT: u8 = 27;
+const S_LABELDEF: u8 = 28; //Identifier followed by ':'
+const START_OP: u8 = 29;   //START, where a '-' can only be an operator
+const S_MINUS_OP: u8 = 30; //- that can't start a literal
+const S_BADINT: u8 = 31;   //Literal run into an identifier character
+const NUM_STATES: usize = 32;
+
+//What an accepting state recognized
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Lexeme {
+    Int,
+    BadInt,
+    Ident,
+    LabelDef,
+    Plus,
+    Minus,
+    Times,
+    Div,
+    Lt,
+    Eq,
+    Arrow,
+    LParen,
+    RParen,
+    Percent,
+    Dollar,
+    CommentOpen,
+    CommentClose,
+    LineComment,
+    Blank,
+    Newline,
+}
+
+const fn build_classes() -> [u8; 256] {
+    let mut t = [C_OTHER as u8; 256];
+    let mut b = b'a';
+    while b <= b'z' {
+        t[b as usize] = C_ALPHA as u8;
+        t[(b - b'a' + b'A') as usize] = C_ALPHA as u8;
+        b += 1
+    }
+    b = b'2';
+    while b <= b'9' {
+        t[b as usize] = C_DIGIT as u8;
+        b += 1
+    }
+    b = b'a';
+    while b <= b'f' {
+        t[b as usize] = C_HEX as u8;
+        t[(b - b'a' + b'A') as usize] = C_HEX as u8;
+        b += 1
+    }
+    t[b'0' as usize] = C_ZERO as u8;
+    t[b'1' as usize] = C_ONE as u8;
+    t[b'b' as usize] = C_B as u8;
+    t[b'x' as usize] = C_X as u8;
+    t[b'-' as usize] = C_MINUS as u8;
+    t[b'+' as usize] = C_PLUS as u8;
+    t[b'*' as usize] = C_STAR as u8;
+    t[b'/' as usize] = C_SLASH as u8;
+    t[b'$' as usize] = C_DOLLAR as u8;
+    t[b' ' as usize] = C_BLANK as u8;
+    t[b'\t' as usize] = C_BLANK as u8;
+    t[b'\r' as usize] = C_CR as u8;
+    t[b'\n' as usize] = C_LF as u8;
+    t[b'_' as usize] = C_UNDER as u8;
+    t[b'(' as usize] = C_LPAREN as u8;
+    t[b')' as usize] = C_RPAREN as u8;
+    t[b'<' as usize] = C_LT as u8;
+    t[b'=' as usize] = C_EQ as u8;
+    t[b'>' as usize] = C_GT as u8;
+    t[b'%' as usize] = C_PERCENT as u8;
+    t[b':' as usize] = C_COLON as u8;
+    t
+}
+
+const fn build_delta() -> [[u8; NUM_CLASSES]; NUM_STATES] {
+    let mut d = [[DEAD; NUM_CLASSES]; NUM_STATES];
+    let mut c = 0;
+    while c < NUM_CLASSES {
+        //Everything up to a newline continues a line comment
+        if c != C_CR && c != C_LF { d[S_LINE as usize][c] = S_LINE }
+        c += 1
+    }
+    d[START as usize][C_ZERO] = S_ZERO;
+    d[START as usize][C_ONE] = S_DEC;
+    d[START as usize][C_DIGIT] = S_DEC;
+    d[START as usize][C_MINUS] = S_MINUS;
+    d[START as usize][C_PLUS] = S_PLUS;
+    d[START as usize][C_STAR] = S_STAR;
+    d[START as usize][C_SLASH] = S_SLASH;
+    d[START as usize][C_DOLLAR] = S_DOLLAR;
+    d[START as usize][C_BLANK] = S_BLANK;
+    d[START as usize][C_CR] = S_CR;
+    d[START as usize][C_LF] = S_LF;
+    d[S_MINUS as usize][C_ZERO] = S_ZERO;
+    d[S_MINUS as usize][C_ONE] = S_DEC;
+    d[S_MINUS as usize][C_DIGIT] = S_DEC;
+    d[S_ZERO as usize][C_ZERO] = S_DEC;
+    d[S_ZERO as usize][C_ONE] = S_DEC;
+    d[S_ZERO as usize][C_DIGIT] = S_DEC;
+    d[S_ZERO as usize][C_X] = S_HEX0;
+    d[S_ZERO as usize][C_B] = S_BIN0;
+    d[S_DEC as usize][C_ZERO] = S_DEC;
+    d[S_DEC as usize][C_ONE] = S_DEC;
+    d[S_DEC as usize][C_DIGIT] = S_DEC;
+    let hex = [C_ZERO, C_ONE, C_DIGIT, C_B, C_HEX];
+    let mut i = 0;
+    while i < hex.len() {
+        d[S_HEX0 as usize][hex[i]] = S_HEX;
+        d[S_HEX as usize][hex[i]] = S_HEX;
+        i += 1
+    }
+    d[S_BIN0 as usize][C_ZERO] = S_BIN;
+    d[S_BIN0 as usize][C_ONE] = S_BIN;
+    d[S_BIN as usize][C_ZERO] = S_BIN;
+    d[S_BIN as usize][C_ONE] = S_BIN;
+    //Any other identifier character makes the literal malformed
+    let word = [C_ZERO, C_ONE, C_DIGIT, C_B, C_X, C_HEX, C_ALPHA, C_UNDER];
+    let lits = [S_ZERO, S_DEC, S_HEX0, S_HEX, S_BIN0, S_BIN, S_BADINT];
+    i = 0;
+    while i < lits.len() {
+        let mut j = 0;
+        while j < word.len() {
+            let next = &mut d[lits[i] as usize][word[j]];
+            if *next == DEAD { *next = S_BADINT }
+            j += 1
+        }
+        i += 1
+    }
+    d[S_STAR as usize][C_SLASH] = S_CLOSE;
+    d[S_SLASH as usize][C_STAR] = S_OPEN;
+    d[S_SLASH as usize][C_SLASH] = S_LINE;
+    d[S_CR as usize][C_LF] = S_CRLF;
+    let letters = [C_ALPHA, C_B, C_X, C_HEX, C_UNDER];
+    i = 0;
+    while i < letters.len() {
+        d[START as usize][letters[i]] = S_ID;
+        d[S_ID as usize][letters[i]] = S_ID;
+        i += 1
+    }
+    d[S_ID as usize][C_ZERO] = S_ID;
+    d[S_ID as usize][C_ONE] = S_ID;
+    d[S_ID as usize][C_DIGIT] = S_ID;
+    d[S_ID as usize][C_COLON] = S_LABELDEF;
+    d[START as usize][C_LPAREN] = S_LPAREN;
+    d[START as usize][C_RPAREN] = S_RPAREN;
+    d[START as usize][C_LT] = S_LT;
+    d[START as usize][C_EQ] = S_EQ1;
+    d[START as usize][C_PERCENT] = S_PERCENT;
+    d[S_EQ1 as usize][C_EQ] = S_EQ;
+    d[S_MINUS as usize][C_GT] = S_ARROW;
+    d[START_OP as usize] = d[START as usize];
+    d[START_OP as usize][C_MINUS] = S_MINUS_OP;
+    d[S_MINUS_OP as usize][C_GT] = S_ARROW;
+    d
+}
+
+static CLASSES: [u8; 256] = build_classes();
+static DELTA: [[u8; NUM_CLASSES]; NUM_STATES] = build_delta();
+
+fn accepts(state: u8) -> Option<Lexeme> {
+    match state {
+        S_ZERO | S_DEC | S_HEX | S_BIN => Some(Lexeme::Int),
+        S_HEX0 | S_BIN0 | S_BADINT => Some(Lexeme::BadInt),
+        S_ID => Some(Lexeme::Ident),
+        S_LABELDEF => Some(Lexeme::LabelDef),
+        S_PLUS => Some(Lexeme::Plus),
+        S_MINUS | S_MINUS_OP => Some(Lexeme::Minus),
+        S_STAR => Some(Lexeme::Times),
+        S_SLASH => Some(Lexeme::Div),
+        S_LT => Some(Lexeme::Lt),
+        S_EQ => Some(Lexeme::Eq),
+        S_ARROW => Some(Lexeme::Arrow),
+        S_LPAREN => Some(Lexeme::LParen),
+        S_RPAREN => Some(Lexeme::RParen),
+        S_PERCENT => Some(Lexeme::Percent),
+        S_DOLLAR => Some(Lexeme::Dollar),
+        S_OPEN => Some(Lexeme::CommentOpen),
+        S_CLOSE => Some(Lexeme::CommentClose),
+        S_LINE => Some(Lexeme::LineComment),
+        S_BLANK => Some(Lexeme::Blank),
+        S_CR | S_CRLF | S_LF => Some(Lexeme::Newline),
+        _ => None
+    }
+}
+
+//The value of an integer literal accepted by the DFA, or None if it
+//doesn't fit in an i32.
+pub fn int_of_literal(lit: &str) -> Option<i32> {
+    let (neg, mag) =
+        if lit.starts_with('-') { (true, lit.split_at(1).1) }
+        else { (false, lit) };
+    let (radix, digits) =
+        if mag.starts_with("0x") { (16, mag.split_at(2).1) }
+        else if mag.starts_with("0b") { (2, mag.split_at(2).1) }
+        else { (10, mag) };
+    let n = i64::from_str_radix(digits, radix).ok()?;
+    let n = if neg { -n } else { n };
+    if n < i32::MIN as i64 || n > i32::MAX as i64 { None }
+    else { Some(n as i32) }
+}
+
+//A run of the DFA over a prefix of the input, which can be resumed when
+//more input arrives.
+struct Scan {
+    state: u8,
+    //Number of bytes fed to the DFA
+    seen: usize,
+    //The longest lexeme accepted so far, and its length in bytes
+    last: Option<(Lexeme, usize)>,
+}
+
+impl Scan {
+    fn new(state: u8) -> Self {
+        Scan{state, seen: 0, last: None}
+    }
+
+    //Feed the DFA the bytes of s it hasn't seen yet. Returns true if
+    //more input could extend the lexeme.
+    fn run(&mut self, s: &[u8]) -> bool {
+        for b in s.split_at(self.seen).1 {
+            self.state = DELTA[self.state as usize][CLASSES[*b as usize] as usize];
+            self.seen += 1;
+            if self.state == DEAD { return false }
+            if let Some(lx) = accepts(self.state) { self.last = Some((lx, self.seen)) }
+        }
+        DELTA[self.state as usize].iter().any(|next| *next != DEAD)
+    }
+}
+
+/* The input to the scanner is a window of bytes that, for streaming
+   sources, can be extended with more input (see stream_lexer.rs). */
+pub trait Source {
+    //The unconsumed input read so far
+    fn window(&self) -> &[u8];
+    //Read more input into the window. Returns false at end of input.
+    fn fill(&mut self) -> io::Result<bool>;
+    fn consume(&mut self, n: usize);
+}
+
+impl Source for &str {
+    fn window(&self) -> &[u8] { self.as_bytes() }
+    fn fill(&mut self) -> io::Result<bool> { Ok(false) }
+    fn consume(&mut self, n: usize) { *self = self.split_at(n).1 }
+}
+
+//The input lex skips between tokens
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Trivia {
+    //A run of spaces and tabs
+    Whitespace,
+    //One \n, \r\n or \r
+    Newline,
+    //From // to the end of the line, excluding the line break
+    LineComment,
+    //From /* to its matching */, including any nested comments
+    BlockComment,
+}
+
+//Lexer state that persists between tokens
+#[derive(Debug,Clone)]
+pub struct Context {
+    //Positions of the currently open /*, outermost first
+    comment_stack: Vec<LineInfo>,
+    //Was a line comment cut off by the end of the window?
+    in_line_comment: bool,
+    //The trivia skipped so far, if it's being kept
+    trivia: Option<Vec<(Trivia, Span)>>,
+    //Does the next token follow an operand, so that a '-' is a minus
+    //operator? Set by infix lexers before each token.
+    pub after_operand: bool,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Context{comment_stack: vec![], in_line_comment: false, trivia: None, after_operand: false}
+    }
+
+    //A context that keeps the trivia lex skips (see lossless.rs)
+    pub fn lossless() -> Self {
+        Context{trivia: Some(vec![]), ..Context::new()}
+    }
+
+    //The trivia kept since the last call, in input order
+    pub fn take_trivia(&mut self) -> Vec<(Trivia, Span)> {
+        self.trivia.as_mut().map_or(vec![], std::mem::take)
+    }
+
+    //Keep the trivia from start to end, if trivia is being kept. Pieces
+    //of a line comment or blank split by a window edge are rejoined.
+    fn keep(&mut self, kind: Trivia, start: &LineInfo, end: &LineInfo) {
+        if let Some(ref mut trivia) = self.trivia {
+            match trivia.last_mut() {
+                Some((last, span)) if *last == kind && span.end == *start
+                    && (kind == Trivia::LineComment || kind == Trivia::Whitespace) =>
+                    span.end = end.clone(),
+                _ => trivia.push((kind, Span{start: start.clone(), end: end.clone()}))
+            }
+        }
+    }
+}
+
+//The first character of s and its length in bytes, or None if s ends
+//partway through one. Invalid UTF-8 is a one-byte REPLACEMENT_CHARACTER.
+fn first_char(s: &[u8]) -> Option<(char, usize)> {
+    let prefix = s.split_at(s.len().min(4)).0;
+    let valid = match str::from_utf8(prefix) {
+        Ok(p) => p,
+        Err(e) if e.valid_up_to() > 0 => str::from_utf8(prefix.split_at(e.valid_up_to()).0).unwrap(),
+        Err(e) if e.error_len().is_none() && s.len() < 4 => return None,
+        Err(_) => return Some((char::REPLACEMENT_CHARACTER, 1))
+    };
+    valid.chars().next().map(|c| (c, c.len_utf8()))
+}
+
+//The first character of the window, reading more input if the window
+//ends partway through it.
+fn peek_char<S: Source>(src: &mut S) -> io::Result<(char, usize)> {
+    loop {
+        match first_char(src.window()) {
+            Some(c) => return Ok(c),
+            None => if !src.fill()? {
+                return Ok((char::REPLACEMENT_CHARACTER, src.window().len()))
+            }
+        }
+    }
+}
+
+//The length of the longest prefix of s that doesn't end partway
+//through a character.
+fn char_boundary(s: &[u8]) -> usize {
+    let mut i = s.len();
+    while i > 0 && s.len() - i < 3 && s[i - 1] & 0xC0 == 0x80 { i -= 1 }
+    if i == 0 { return s.len() }
+    let lead = s[i - 1];
+    let len = if lead >= 0xF0 { 4 } else if lead >= 0xE0 { 3 } else if lead >= 0xC0 { 2 } else { 1 };
+    if i - 1 + len > s.len() { i - 1 } else { s.len() }
+}
+
+//Advance past the next n bytes of input, which don't include a line break.
+fn advance<S: Source>(src: &mut S, info: &mut LineInfo, n: usize) {
+    info.incr_text(src.window().split_at(n).0);
+    src.consume(n)
+}
+
+//The span of text starting at start.
+fn span_of(start: LineInfo, text: &str) -> Span {
+    let end = start.advanced(text);
+    Span{start, end}
+}
+
+//Lex the next token and its span, skipping whitespace, newlines and
+//(possibly nested) comments. Trivia is consumed in a loop rather than
+//by recursion, so arbitrarily long comments or runs of blank lines use
+//constant stack space. The input an error is reported for is consumed,
+//so lexing can resume after it.
+pub fn lex<S: Source, T: Vocab>(src: &mut S, info: &mut LineInfo, cx: &mut Context)
+                                -> Result<(T, Span), LexError> {
+    let read_err = |err: io::Error, info: &LineInfo| ReadError(err.to_string(), span_of(info.clone(), ""));
+    loop {
+        let start = info.clone();
+        let from = if cx.in_line_comment { S_LINE } else if cx.after_operand { START_OP } else { START };
+        let mut scan = Scan::new(from);
+        //Was the lexeme cut off by a full window?
+        let cut = loop {
+            if !scan.run(src.window()) { break false }
+            //The lexeme might continue past the window
+            match src.fill() {
+                Ok(true) => {},
+                Ok(false) => break false,
+                //Comment text needn't fit in the window: skip what was read
+                Err(_) if scan.last.is_some() && (!cx.comment_stack.is_empty() || cx.in_line_comment
+                                                  || scan.last.unwrap().0 == Lexeme::LineComment) => break true,
+                Err(err) => return Err(read_err(err, info))
+            }
+        };
+        let mut res = scan.last;
+        let line_comment = res.map(|(lx, _)| lx) == Some(Lexeme::LineComment);
+        if cx.in_line_comment || (cut && line_comment && cx.comment_stack.is_empty()) {
+            //Continue the line comment in the next window if it was cut
+            //off, leaving any partial character for the next window
+            cx.in_line_comment = cut;
+            let n = res.map_or(0, |(_, n)| n);
+            let n = if cut { char_boundary(src.window().split_at(n).0) } else { n };
+            res = Some((Lexeme::LineComment, n))
+        }
+        match res {
+            Some((Lexeme::CommentOpen, n)) => {
+                cx.comment_stack.push(start);
+                advance(src, info, n)
+            },
+            Some((Lexeme::CommentClose, n)) => {
+                let open = cx.comment_stack.pop();
+                advance(src, info, n);
+                let open = match open {
+                    Some(open) => open,
+                    None => return Err(UnbalancedCommentClose(Span{start, end: info.clone()}))
+                };
+                if cx.comment_stack.is_empty() { cx.keep(Trivia::BlockComment, &open, info) }
+            },
+            Some((Lexeme::Blank, n)) => {
+                advance(src, info, n);
+                if cx.comment_stack.is_empty() { cx.keep(Trivia::Whitespace, &start, info) }
+            },
+            Some((Lexeme::LineComment, n)) => {
+                //Within a block comment, skip just the //
+                let n = if cx.comment_stack.is_empty() { n } else { 2 };
+                advance(src, info, n);
+                if cx.comment_stack.is_empty() { cx.keep(Trivia::LineComment, &start, info) }
+            },
+            Some((Lexeme::Newline, n)) => {
+                info.incr_line(n as u64);
+                src.consume(n);
+                if cx.comment_stack.is_empty() { cx.keep(Trivia::Newline, &start, info) }
+            },
+            Some((lx, n)) => {
+                if !cx.comment_stack.is_empty() {
+                    advance(src, info, n);
+                    continue
+                }
+                //Lexemes other than trivia are ASCII
+                let text = str::from_utf8(src.window().split_at(n).0).unwrap();
+                let span = span_of(start, text);
+                let tok = if lx == Lexeme::BadInt { Err(MalformedInteger(text.to_string(), span.clone())) }
+                          else { T::token(lx, text, &span) };
+                advance(src, info, n);
+                return tok.map(|tok| (tok, span))
             },
             None => {
                 //Fall-through cases
-                if s.len() > 0 {
-                    if l.comment_depth > 0 {
+                if !src.window().is_empty() {
+                    let (c, n) = peek_char(src).map_err(|err| read_err(err, info))?;
+                    if !cx.comment_stack.is_empty() {
                         //1. Currently lexing a comment
-                        l.info.incr_col(1);
-                        l.rest = l.rest.split_at(1).1;
-                        lex(l)
+                        info.incr_char(c);
+                        src.consume(n)
                     } else {
-                        //2. Otherwise, saw an unexpected token
-                        Err(format!(r"unexpected token '{}'", s.split_at(1).0))
+                        //2. Otherwise, saw an unexpected character
+                        info.incr_char(c);
+                        src.consume(n);
+                        return Err(UnexpectedChar(c, Span{start, end: info.clone()}))
                     }
+                } else if !cx.comment_stack.is_empty() {
+                    //3. Input ended inside a comment: report the outermost
+                    //unclosed opener
+                    let open = cx.comment_stack.swap_remove(0);
+                    return Err(UnterminatedComment(span_of(open, "/*")))
                 } else {
-                    //3. A token was requested but none exists
-                    Err(format!("unexpected end of program"))
+                    //4. A token was requested but none exists
+                    return Err(UnexpectedEof(span_of(start, "")))
                 }
             }
         }
     }
 }
 
+/* A token vocabulary turns the lexemes recognized by the scanner into
+   tokens. Tok is the GrumpyIR vocabulary; asm_lexer.rs defines the
+   one for Grumpy assembly. */
+pub trait Vocab: Sized + Clone {
+    fn token(lx: Lexeme, text: &str, span: &Span) -> Result<Self, LexError>;
+
+    //Can the token be the last of an operand, e.g. a literal or )?
+    fn ends_operand(&self) -> bool {
+        false
+    }
+}
+
+impl Vocab for Tok {
+    fn token(lx: Lexeme, text: &str, span: &Span) -> Result<Tok, LexError> {
+        Ok(match lx {
+            Lexeme::Plus => Tok::PLUS,
+            Lexeme::Minus => Tok::MINUS,
+            Lexeme::Times => Tok::TIMES,
+            Lexeme::Div => Tok::DIV,
+            Lexeme::Lt => Tok::LT,
+            Lexeme::Eq => Tok::EQ,
+            Lexeme::Arrow => Tok::ARROW,
+            Lexeme::LParen => Tok::LPAREN,
+            Lexeme::RParen => Tok::RPAREN,
+            Lexeme::Percent => Tok::PERCENT,
+            Lexeme::Dollar => Tok::DOLLAR,
+            Lexeme::Ident => keyword(text).unwrap_or_else(|| Tok::ID(text.to_string())),
+            Lexeme::Int => match int_of_literal(text) {
+                Some(i) => Tok::I32(i),
+                None => return Err(IntegerOverflow(text.to_string(), span.clone()))
+            },
+            Lexeme::LabelDef => {
+                let start = span.start.advanced(text.trim_end_matches(':'));
+                return Err(UnexpectedChar(':', Span{start, end: span.end.clone()}))
+            },
+            _ => unreachable!()
+        })
+    }
+
+    fn ends_operand(&self) -> bool {
+        matches!(self, Tok::I32(_) | Tok::ID(_) | Tok::TRUE | Tok::FALSE | Tok::TT | Tok::RPAREN)
+    }
+}
+
+/* The interface parsers use to pull GrumpyIR tokens, implemented by
+   both LexerState and StreamLexer (stream_lexer.rs). */
+pub trait TokenStream {
+    //Like peek, but also returns the span of the token.
+    fn peek_spanned(&mut self) -> Result<(Tok, Span), LexError>;
+    //Like next, but also returns the span of the token.
+    fn next_spanned(&mut self) -> Result<(Tok, Span), LexError>;
+    //The position just past the last token consumed
+    fn info(&self) -> LineInfo;
+
+    //Skip ahead to info, the position just past a ) after the next
+    //token, as if the tokens before it had been consumed. Returns false
+    //if the stream can't skip.
+    fn skip_to(&mut self, _info: LineInfo) -> bool {
+        false
+    }
+
+    fn peek(&mut self) -> Result<Tok, LexError> {
+        self.peek_spanned().map(|(tok, _)| tok)
+    }
+
+    fn next(&mut self) -> Result<Tok, LexError> {
+        self.next_spanned().map(|(tok, _)| tok)
+    }
+
+    fn eat(&mut self, expected: Tok) -> Result<Tok, LexError> {
+        let (found, span) = self.next_spanned()?;
+        if found == expected { Ok(found) }
+        else { Err(UnexpectedToken{expected, found, span}) }
+    }
+}
+
 #[derive(Debug,Clone)]
-pub struct LexerState<'a> {
-    comment_depth: u64,
-    pub rest: &'a str,    
+pub struct LexerState<'a, T = Tok> {
+    cx: Context,
+    pub rest: &'a str,
     pub info: LineInfo,
+    //A token already lexed by peek, with the input and position after it
+    peeked: Option<(T, Span, &'a str, LineInfo)>,
+    //Is a '-' after an operand a minus operator? (See lexer.rs's rule
+    //for literals.)
+    infix: bool,
+    //Did the last token consumed end an operand?
+    after_operand: bool,
 }
 
 impl<'a> LexerState<'a> {
     pub fn new(s: &'a str) -> Self {
+        LexerState::init(s)
+    }
+
+    //A lexer for infix expressions, in which '-' after an operand is
+    //always the minus operator
+    pub fn infix(s: &'a str) -> Self {
+        LexerState{infix: true, ..LexerState::init(s)}
+    }
+}
+
+impl<'a, T: Vocab> LexerState<'a, T> {
+    //A lexer for s in vocabulary T
+    pub fn init(s: &'a str) -> Self {
         LexerState{
-            comment_depth: 0,
-            rest: s.trim_end(),            
-            info: LineInfo{line_no: 1, col_no: 0},
+            cx: Context::new(),
+            rest: s.trim_end(),
+            info: LineInfo::start(),
+            peeked: None,
+            infix: false,
+            after_operand: false,
         }
     }
 
-    pub fn peek(self: &mut LexerState<'a>) -> Option<Tok> {
-        let revert = self.clone();
-        match lex(self) {
-            Ok(tok) => {
-                *self = revert;
-                Some(tok)
+    //Like peek, but also returns the span of the token.
+    pub fn peek_spanned(self: &mut LexerState<'a, T>) -> Result<(T, Span), LexError> {
+        if let Some((ref tok, ref span, _, _)) = self.peeked {
+            return Ok((tok.clone(), span.clone()))
+        }
+        let (rest, info) = (self.rest, self.info.clone());
+        self.cx.after_operand = self.infix && self.after_operand;
+        let res: Result<(T, Span), LexError> = lex(&mut self.rest, &mut self.info, &mut self.cx);
+        if let Ok((ref tok, ref span)) = res {
+            self.peeked = Some((tok.clone(), span.clone(), self.rest, self.info.clone()));
+        }
+        self.rest = rest;
+        self.info = info;
+        self.cx = Context::new();
+        res
+    }
+
+    pub fn peek(self: &mut LexerState<'a, T>) -> Result<T, LexError> {
+        self.peek_spanned().map(|(tok, _)| tok)
+    }
+
+    //Like next, but also returns the span of the token.
+    pub fn next_spanned(self: &mut LexerState<'a, T>) -> Result<(T, Span), LexError> {
+        let res = match self.peeked.take() {
+            Some((tok, span, rest, info)) => {
+                self.rest = rest;
+                self.info = info;
+                Ok((tok, span))
             },
-            Err(err) => {
-                eprintln!("lexer error: {} at {}:{}",
-                          err, self.info.line_no, self.info.col_no);
-                None
+            None => {
+                self.cx.after_operand = self.infix && self.after_operand;
+                lex(&mut self.rest, &mut self.info, &mut self.cx)
             }
+        };
+        if let Ok((ref tok, _)) = res { self.after_operand = tok.ends_operand() }
+        res
+    }
+
+    pub fn next(self: &mut LexerState<'a, T>) -> Result<T, LexError> {
+        self.next_spanned().map(|(tok, _)| tok)
+    }
+}
+
+impl<'a> TokenStream for LexerState<'a> {
+    fn peek_spanned(&mut self) -> Result<(Tok, Span), LexError> {
+        LexerState::peek_spanned(self)
+    }
+
+    fn next_spanned(&mut self) -> Result<(Tok, Span), LexError> {
+        LexerState::next_spanned(self)
+    }
+
+    fn info(&self) -> LineInfo {
+        self.info.clone()
+    }
+
+    fn skip_to(&mut self, info: LineInfo) -> bool {
+        let n = match info.byte_offset.checked_sub(self.info.byte_offset) {
+            Some(n) => n as usize,
+            None => return false
+        };
+        match self.rest.get(n..) {
+            Some(rest) => {
+                self.rest = rest;
+                self.info = info;
+                self.peeked = None;
+                self.cx = Context::new();
+                self.after_operand = true;
+                true
+            },
+            None => false
         }
     }
-    
-    pub fn next(self: &mut LexerState<'a>) -> Option<Tok> {
-        match lex(self) {
-            Ok(tok) => Some(tok),
-            Err(err) => {
-                eprintln!(r"lexer error: {} at {}:{}",
-                          err, self.info.line_no, self.info.col_no);
-                None
-            }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BIG: usize = 1_000_000;
+
+    #[test]
+    fn long_comment() {
+        let s = format!("/*{}*/ 7 $", "x".repeat(BIG));
+        let mut l = LexerState::new(&s);
+        assert_eq!(l.next(), Ok(Tok::I32(7)));
+        assert_eq!(l.next(), Ok(Tok::DOLLAR));
+        assert_eq!(l.info.line_no, 1);
+    }
+
+    #[test]
+    fn long_comment_with_tokens_and_newlines() {
+        let s = format!("1 /*{}*/ + 2 $", "3 + 4 *\n".repeat(BIG / 8));
+        let mut l = LexerState::new(&s);
+        assert_eq!(l.next(), Ok(Tok::I32(1)));
+        assert_eq!(l.next(), Ok(Tok::PLUS));
+        assert_eq!(l.info.line_no, 1 + (BIG / 8) as u64);
+        assert_eq!(l.next(), Ok(Tok::I32(2)));
+    }
+
+    #[test]
+    fn deeply_nested_comment() {
+        let s = format!("{}{} 5 $", "/*".repeat(BIG / 4), "*/".repeat(BIG / 4));
+        let mut l = LexerState::new(&s);
+        assert_eq!(l.next(), Ok(Tok::I32(5)));
+    }
+
+    #[test]
+    fn long_blank_lines() {
+        let s = format!("{}\t 9", "\r\n".repeat(BIG));
+        let mut l = LexerState::new(&s);
+        assert_eq!(l.next(), Ok(Tok::I32(9)));
+        assert_eq!(l.info.line_no, 1 + BIG as u64);
+        assert_eq!(l.info.col_no, 3);
+    }
+
+    fn pos(line_no: u64, col_no: u64, byte_offset: u64) -> LineInfo {
+        LineInfo{line_no, col_no, display_col: col_no, byte_offset}
+    }
+
+    #[test]
+    fn errors_carry_spans() {
+        let mut l = LexerState::new("1 +\n  ?");
+        l.next().unwrap();
+        l.next().unwrap();
+        assert_eq!(l.peek(), Err(UnexpectedChar('?', Span{start: pos(2, 2, 6), end: pos(2, 3, 7)})));
+        assert_eq!(l.next(), Err(UnexpectedChar('?', Span{start: pos(2, 2, 6), end: pos(2, 3, 7)})));
+
+        let mut l = LexerState::new("1");
+        l.next().unwrap();
+        assert_eq!(l.next(), Err(UnexpectedEof(Span{start: pos(1, 1, 1), end: pos(1, 1, 1)})));
+
+        let mut l = LexerState::new("12 99999999999");
+        l.next().unwrap();
+        assert_eq!(l.next(), Err(IntegerOverflow("99999999999".to_string(),
+                                                 Span{start: pos(1, 3, 3), end: pos(1, 14, 14)})));
+
+
+        let mut l = LexerState::new("1 +");
+        assert_eq!(l.eat(Tok::I32(1)), Ok(Tok::I32(1)));
+        assert!(matches!(l.eat(Tok::TIMES),
+                         Err(UnexpectedToken{expected: Tok::TIMES, found: Tok::PLUS, ..})));
+    }
+
+    #[test]
+    fn unbalanced_comments() {
+        let mut l = LexerState::new("1 /* 2\n /* 3 */");
+        l.next().unwrap();
+        assert_eq!(l.next(), Err(UnterminatedComment(Span{start: pos(1, 2, 2), end: pos(1, 4, 4)})));
+
+        let mut l = LexerState::new("/* a */ /* b\n  /* c */\n /* d ");
+        assert_eq!(l.next(), Err(UnterminatedComment(Span{start: pos(1, 8, 8), end: pos(1, 10, 10)})));
+
+        let mut l = LexerState::new("1 /* a */\n + 2 */ 3");
+        assert_eq!(l.next(), Ok(Tok::I32(1)));
+        assert_eq!(l.next(), Ok(Tok::PLUS));
+        assert_eq!(l.next(), Ok(Tok::I32(2)));
+        assert_eq!(l.next(), Err(UnbalancedCommentClose(Span{start: pos(2, 5, 15), end: pos(2, 7, 17)})));
+    }
+
+    #[test]
+    fn literals_and_line_comments() {
+        let s = "0 -7 007 0x1F -0x80000000 0b101 // 1 + /* 2\n 2147483647 -2147483648 $";
+        let mut l = LexerState::new(s);
+        for i in &[0, -7, 7, 31, i32::MIN, 5] {
+            assert_eq!(l.next(), Ok(Tok::I32(*i)));
         }
+        assert_eq!(l.next(), Ok(Tok::I32(i32::MAX)));
+        assert_eq!(l.info.line_no, 2);
+        assert_eq!(l.next(), Ok(Tok::I32(i32::MIN)));
+        assert_eq!(l.next(), Ok(Tok::DOLLAR));
+
+        let mut l = LexerState::new("/* // */ 1");
+        assert_eq!(l.next(), Ok(Tok::I32(1)));
+
+        for lit in &["99999999999", "2147483648", "-2147483649", "0xFFFFFFFF", "0b100000000000000000000000000000000"] {
+            let mut l = LexerState::new(lit);
+            assert!(matches!(l.next(), Err(IntegerOverflow(..))), "{}", lit);
+        }
+        for lit in &["0x", "0b", "-0x", "0b2", "0x1g", "12ab", "0_1", "0b101x2"] {
+            let mut l = LexerState::new(lit);
+            assert_eq!(l.next(), Err(MalformedInteger(lit.to_string(), span_of(pos(1, 0, 0), lit))), "{}", lit);
+        }
+        let mut l = LexerState::new("1 0x $");
+        l.next().unwrap();
+        assert_eq!(l.next(), Err(MalformedInteger("0x".to_string(), Span{start: pos(1, 2, 2), end: pos(1, 4, 4)})));
+        assert_eq!(l.next(), Ok(Tok::DOLLAR));
+        let mut l = LexerState::new("- 4");
+        assert_eq!(l.next(), Ok(Tok::MINUS));
+    }
+
+    #[test]
+    fn minus_after_an_operand() {
+        fn toks(mut l: LexerState) -> Vec<Tok> {
+            let mut toks = vec![];
+            while let Ok(tok) = l.next() { toks.push(tok) }
+            toks
+        }
+        use self::Tok::*;
+        let s = "(- 3 -4) 1-1 x -2 tt-0x1 (-5)-6 -7 -> 8->";
+        assert_eq!(toks(LexerState::new(s)), vec![
+            LPAREN, MINUS, I32(3), I32(-4), RPAREN, I32(1), I32(-1), ID("x".to_string()), I32(-2),
+            TT, I32(-1), LPAREN, I32(-5), RPAREN, I32(-6), I32(-7), ARROW, I32(8), ARROW]);
+        assert_eq!(toks(LexerState::infix(s)), vec![
+            LPAREN, MINUS, I32(3), MINUS, I32(4), RPAREN, I32(1), MINUS, I32(1), ID("x".to_string()), MINUS, I32(2),
+            TT, MINUS, I32(1), LPAREN, I32(-5), RPAREN, MINUS, I32(6), MINUS, I32(7), ARROW, I32(8), ARROW]);
+        //A peeked token is lexed in the same way
+        let mut l = LexerState::infix("1 -2");
+        l.next().unwrap();
+        assert_eq!(l.peek(), Ok(MINUS));
+        assert_eq!(l.next(), Ok(MINUS));
+        assert_eq!(l.next(), Ok(I32(2)));
+    }
+
+    #[test]
+    fn grumpy_ir_tokens() {
+        use lexer::Tok::*;
+        let s = "(fun f (x (array i32)) (b bool) -> unit\n  (seq (set x 0 -1) (print (neg b))))\n%\n\
+                 (let _y1 (alloc 2 tt) (cond (< 1 2) (== true false) (call (funptr f) (/ 4 (- 2 1)))))\n\
+                 (spawn (get x0 1)) (fun_ unit)";
+        let mut l = LexerState::new(s);
+        let mut toks = vec![];
+        while let Ok(tok) = l.next() { toks.push(tok) }
+        let id = |s: &str| ID(s.to_string());
+        assert_eq!(toks, vec![
+            LPAREN, FUN, id("f"), LPAREN, id("x"), LPAREN, ARRAYTY, I32TY, RPAREN, RPAREN,
+            LPAREN, id("b"), BOOLTY, RPAREN, ARROW, UNITTY,
+            LPAREN, SEQ, LPAREN, SET, id("x"), I32(0), I32(-1), RPAREN,
+            LPAREN, PRINT, LPAREN, NEG, id("b"), RPAREN, RPAREN, RPAREN, RPAREN, PERCENT,
+            LPAREN, LET, id("_y1"), LPAREN, ALLOC, I32(2), TT, RPAREN,
+            LPAREN, COND, LPAREN, LT, I32(1), I32(2), RPAREN, LPAREN, EQ, TRUE, FALSE, RPAREN,
+            LPAREN, CALL, LPAREN, FUNPTR, id("f"), RPAREN,
+            LPAREN, DIV, I32(4), LPAREN, MINUS, I32(2), I32(1), RPAREN, RPAREN, RPAREN, RPAREN, RPAREN,
+            LPAREN, SPAWN, LPAREN, GET, id("x0"), I32(1), RPAREN, RPAREN,
+            LPAREN, id("fun_"), UNITTY, RPAREN]);
+
+        let mut l = LexerState::new("  (\n foo =");
+        assert_eq!(l.next_spanned(), Ok((LPAREN, Span{start: pos(1, 2, 2), end: pos(1, 3, 3)})));
+        assert_eq!(l.peek_spanned(), Ok((id("foo"), Span{start: pos(2, 1, 5), end: pos(2, 4, 8)})));
+        assert_eq!(l.next(), Ok(id("foo")));
+        assert_eq!(l.next(), Err(UnexpectedChar('=', Span{start: pos(2, 5, 9), end: pos(2, 6, 10)})));
     }
-    
-    pub fn eat(self: &mut LexerState<'a>, expected: Tok) -> Option<()> {
-        if let Some(t) = self.next() {
-            if t == expected { Some(()) }
-            else { None }}
-        else { None }
+
+    #[test]
+    fn unicode_columns() {
+        let s = "/* é😀 */ 1\n\t漢x";
+        let mut l = LexerState::new(s);
+        let (tok, span) = l.next_spanned().unwrap();
+        assert_eq!(tok, Tok::I32(1));
+        assert_eq!(span.start, LineInfo{line_no: 1, col_no: 9, display_col: 10, byte_offset: 13});
+        assert_eq!(l.next(), Err(UnexpectedChar('漢', Span{
+            start: LineInfo{line_no: 2, col_no: 1, display_col: 8, byte_offset: 16},
+            end: LineInfo{line_no: 2, col_no: 2, display_col: 10, byte_offset: 19}})));
+        //Multi-byte characters in comments used to split mid-character
+        let mut l = LexerState::new("/* ü */ // ü\n2");
+        assert_eq!(l.next(), Ok(Tok::I32(2)));
     }
 }
-
-    
-    
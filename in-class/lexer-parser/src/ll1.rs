@@ -0,0 +1,408 @@
+use std::collections::{BTreeMap,BTreeSet};
+
+/* An LL(1) parser generator. From a BNF grammar it computes the
+   nullable nonterminals and the FIRST and FOLLOW sets, and from those
+   the predictive parsing table, as done by hand for the arithmetic
+   grammar at the top of parser.rs. If no cell of the table holds more
+   than one production, the grammar is LL(1) and the table drives a
+   parser; otherwise the result is a report of the conflicts.
+
+   Grammars are written as in parser.rs:
+
+     <exp> ::= <term> <exp-rest>
+     <exp-rest> ::= + <term> <exp-rest>
+                  | <empty-string>
+
+   A line may start with a production number, like "2.", which is
+   ignored; productions are numbered in order from 0 regardless. ::==
+   may be written for ::=. A symbol is a nonterminal if it appears to the
+   left of a ::=, and a terminal otherwise, so <i32> above is a terminal.
+   <empty-string> (or nothing) is the empty sequence of symbols. The first
+   nonterminal defined is the start symbol. Nothing marks the end of the
+   input specially: a grammar that needs an end marker mentions $. */
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Symbol {
+    Term(String),
+    Nonterm(String),
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Production {
+    pub lhs: String,
+    pub rhs: Vec<Symbol>,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Grammar {
+    pub start: String,
+    pub productions: Vec<Production>,
+}
+
+const EMPTY: &str = "<empty-string>";
+
+impl Grammar {
+    pub fn parse(s: &str) -> Result<Grammar, String> {
+        //Each production as (lhs, rhs words), before symbols are classified
+        let mut prods: Vec<(String, Vec<&str>)> = vec![];
+        for (i, line) in s.lines().enumerate() {
+            let mut line = line.trim();
+            //Drop a production number
+            if let Some((n, rest)) = line.split_once('.') {
+                if !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()) { line = rest.trim() }
+            }
+            if line.is_empty() { continue }
+            let (lhs, alts) = if let Some(alts) = line.strip_prefix('|') {
+                match prods.last() {
+                    Some((lhs, _)) => (lhs.clone(), alts),
+                    None => return Err(format!("line {}: alternative with no nonterminal", i + 1))
+                }
+            } else {
+                let (lhs, rhs) = match line.split_once("::=") {
+                    Some((lhs, rhs)) => (lhs.trim(), rhs.strip_prefix('=').unwrap_or(rhs)),
+                    None => return Err(format!("line {}: expected ::=", i + 1))
+                };
+                if lhs.is_empty() || lhs.contains(char::is_whitespace) || lhs == EMPTY {
+                    return Err(format!("line {}: expected one nonterminal before ::=, found {:?}", i + 1, lhs))
+                }
+                (lhs.to_string(), rhs)
+            };
+            for rhs in alts.split('|') {
+                prods.push((lhs.clone(), rhs.split_whitespace().filter(|w| *w != EMPTY).collect()))
+            }
+        }
+        let start = match prods.first() {
+            Some((lhs, _)) => lhs.clone(),
+            None => return Err("the grammar has no productions".to_string())
+        };
+        let nonterms: BTreeSet<String> = prods.iter().map(|(lhs, _)| lhs.clone()).collect();
+        let productions = prods.into_iter().map(|(lhs, words)| {
+            let rhs = words.into_iter().map(|w| {
+                if nonterms.contains(w) { Symbol::Nonterm(w.to_string()) } else { Symbol::Term(w.to_string()) }
+            }).collect();
+            Production{lhs, rhs}
+        }).collect();
+        Ok(Grammar{start, productions})
+    }
+
+    //The nonterminals, in the order they're first defined
+    pub fn nonterminals(&self) -> Vec<&str> {
+        let mut ns: Vec<&str> = vec![];
+        for p in self.productions.iter() {
+            if !ns.contains(&p.lhs.as_str()) { ns.push(&p.lhs) }
+        }
+        ns
+    }
+
+    //The terminals, in the order they first appear
+    pub fn terminals(&self) -> Vec<&str> {
+        let mut ts: Vec<&str> = vec![];
+        for sym in self.productions.iter().flat_map(|p| p.rhs.iter()) {
+            if let Symbol::Term(t) = sym {
+                if !ts.contains(&t.as_str()) { ts.push(t) }
+            }
+        }
+        ts
+    }
+}
+
+/*****************************************
+ * Nullable, FIRST and FOLLOW
+ *****************************************/
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Sets {
+    pub nullable: BTreeSet<String>,
+    pub first: BTreeMap<String, BTreeSet<String>>,
+    pub follow: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Sets {
+    //Is the sequence syms nullable?
+    pub fn nullable_seq(&self, syms: &[Symbol]) -> bool {
+        syms.iter().all(|sym| match sym {
+            Symbol::Term(_) => false,
+            Symbol::Nonterm(n) => self.nullable.contains(n)
+        })
+    }
+
+    //The terminals that can start the sequence syms
+    pub fn first_seq(&self, syms: &[Symbol]) -> BTreeSet<String> {
+        let mut first = BTreeSet::new();
+        for sym in syms.iter() {
+            match sym {
+                Symbol::Term(t) => {
+                    first.insert(t.clone());
+                    return first
+                },
+                Symbol::Nonterm(n) => {
+                    first.extend(self.first[n].iter().cloned());
+                    if !self.nullable.contains(n) { return first }
+                }
+            }
+        }
+        first
+    }
+}
+
+//Add xs to the set, returning whether it grew.
+fn add_all(set: &mut BTreeSet<String>, xs: BTreeSet<String>) -> bool {
+    let n = set.len();
+    set.extend(xs);
+    set.len() > n
+}
+
+//Compute the sets for g, each by iterating to a fixed point.
+pub fn sets(g: &Grammar) -> Sets {
+    let empty = || g.nonterminals().into_iter().map(|n| (n.to_string(), BTreeSet::new())).collect();
+    let mut sets = Sets{nullable: BTreeSet::new(), first: empty(), follow: empty()};
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for p in g.productions.iter() {
+            if !sets.nullable.contains(&p.lhs) && sets.nullable_seq(&p.rhs) {
+                sets.nullable.insert(p.lhs.clone());
+                changed = true
+            }
+            let first = sets.first_seq(&p.rhs);
+            changed |= add_all(sets.first.get_mut(&p.lhs).unwrap(), first);
+        }
+    }
+    changed = true;
+    while changed {
+        changed = false;
+        for p in g.productions.iter() {
+            for (i, sym) in p.rhs.iter().enumerate() {
+                if let Symbol::Nonterm(n) = sym {
+                    let rest = &p.rhs[i + 1..];
+                    let mut follow = sets.first_seq(rest);
+                    if sets.nullable_seq(rest) { follow.extend(sets.follow[&p.lhs].iter().cloned()) }
+                    changed |= add_all(sets.follow.get_mut(n).unwrap(), follow);
+                }
+            }
+        }
+    }
+    sets
+}
+
+/*****************************************
+ * The predictive parsing table
+ *****************************************/
+
+/* The table has a cell for each nonterminal and terminal, holding the
+   productions to expand the nonterminal by when the terminal is next in
+   the input: those whose right-hand side can start with the terminal,
+   or can be empty when the terminal can follow the nonterminal. */
+
+pub struct Table {
+    pub grammar: Grammar,
+    pub sets: Sets,
+    //The nonempty cells, holding production numbers
+    pub cells: BTreeMap<(String, String), Vec<usize>>,
+}
+
+//A cell holding more than one production
+#[derive(Debug,Clone,PartialEq)]
+pub struct Conflict {
+    pub nonterm: String,
+    pub term: String,
+    pub productions: Vec<usize>,
+}
+
+pub fn table(g: &Grammar) -> Table {
+    let sets = sets(g);
+    let mut cells: BTreeMap<(String, String), Vec<usize>> = BTreeMap::new();
+    for (i, p) in g.productions.iter().enumerate() {
+        let mut terms = sets.first_seq(&p.rhs);
+        if sets.nullable_seq(&p.rhs) { terms.extend(sets.follow[&p.lhs].iter().cloned()) }
+        for t in terms {
+            cells.entry((p.lhs.clone(), t)).or_default().push(i)
+        }
+    }
+    Table{grammar: g.clone(), sets, cells}
+}
+
+impl Table {
+    pub fn cell(&self, nonterm: &str, term: &str) -> &[usize] {
+        self.cells.get(&(nonterm.to_string(), term.to_string())).map_or(&[], |ps| ps)
+    }
+
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        self.cells.iter().filter(|(_, ps)| ps.len() > 1).map(|((nonterm, term), ps)| {
+            Conflict{nonterm: nonterm.clone(), term: term.clone(), productions: ps.clone()}
+        }).collect()
+    }
+
+    //The sets and the table laid out as in parser.rs, followed by any
+    //conflicts. A conflicting cell lists its productions as 2/3.
+    pub fn report(&self) -> String {
+        let g = &self.grammar;
+        let (nonterms, terms) = (g.nonterminals(), g.terminals());
+        //Terminals in grammar order
+        let list = |set: &BTreeSet<String>| {
+            terms.iter().filter(|t| set.contains(**t)).cloned().collect::<Vec<_>>().join(", ")
+        };
+        let rows: Vec<(&str, String, String)> = nonterms.iter().map(|n| {
+            let first = list(&self.sets.first[*n]);
+            (*n, first, list(&self.sets.follow[*n]))
+        }).collect();
+        let wn = nonterms.iter().map(|n| n.len()).max().unwrap_or(0).max("SYMBOL".len());
+        let wf = rows.iter().map(|r| r.1.len()).max().unwrap_or(0).max("FIRST".len());
+        let mut out = format!("{:<wn$} | {:<wf$} | FOLLOW\n", "SYMBOL", "FIRST");
+        out.push_str(&format!("{}\n", "-".repeat(wn + wf + 15)));
+        for (n, first, follow) in rows.iter() {
+            out.push_str(&format!("{:<wn$} | {:<wf$} | {}\n", n, first, follow).replace(" \n", "\n"))
+        }
+        let cells: Vec<Vec<String>> = nonterms.iter().map(|n| {
+            terms.iter().map(|t| {
+                self.cell(n, t).iter().map(|i| i.to_string()).collect::<Vec<_>>().join("/")
+            }).collect()
+        }).collect();
+        let widths: Vec<usize> = terms.iter().enumerate().map(|(j, t)| {
+            cells.iter().map(|row| row[j].len()).max().unwrap_or(0).max(t.len())
+        }).collect();
+        out.push_str(&format!("\n{:>wn$}", ""));
+        for (t, w) in terms.iter().zip(widths.iter()) { out.push_str(&format!(" | {:<w$}", t)) }
+        out.push_str(&format!("\n{}\n", "-".repeat(wn + widths.iter().map(|w| w + 3).sum::<usize>())));
+        for (n, row) in nonterms.iter().zip(cells.iter()) {
+            let mut line = format!("{:>wn$}", n);
+            for (cell, w) in row.iter().zip(widths.iter()) { line.push_str(&format!(" | {:<w$}", cell)) }
+            out.push_str(line.trim_end());
+            out.push('\n')
+        }
+        let conflicts = self.conflicts();
+        if conflicts.is_empty() {
+            out.push_str("\nThe grammar is LL(1).\n")
+        } else {
+            out.push_str(&format!("\n{} conflict(s):\n", conflicts.len()));
+            for c in conflicts.iter() {
+                out.push_str(&format!("  {} on {}: productions {}\n", c.nonterm, c.term,
+                                      c.productions.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(", ")))
+            }
+        }
+        out
+    }
+
+    //Parse the terminals in input, returning the productions of its
+    //leftmost derivation.
+    pub fn parse(&self, input: &[&str]) -> Result<Vec<usize>, String> {
+        let mut stack = vec![Symbol::Nonterm(self.grammar.start.clone())];
+        let mut derivation = vec![];
+        let mut i = 0;
+        while let Some(top) = stack.pop() {
+            let next = input.get(i);
+            match top {
+                Symbol::Term(t) => match next {
+                    Some(next) if **next == t => i += 1,
+                    Some(next) => return Err(format!("expected {}, found {} at {}", t, next, i)),
+                    None => return Err(format!("expected {}, found end of input", t))
+                },
+                Symbol::Nonterm(n) => {
+                    let next = match next {
+                        Some(next) => next,
+                        None => return Err(format!("expected {}, found end of input", n))
+                    };
+                    let p = match self.cell(&n, next).first() {
+                        Some(p) => *p,
+                        None => return Err(format!("expected {}, found {} at {}", n, next, i))
+                    };
+                    derivation.push(p);
+                    stack.extend(self.grammar.productions[p].rhs.iter().rev().cloned())
+                }
+            }
+        }
+        if i < input.len() { return Err(format!("unexpected {} after the end at {}", input[i], i)) }
+        Ok(derivation)
+    }
+}
+
+//The table for g if g is LL(1), or else a report of its conflicts
+pub fn generate(g: &Grammar) -> Result<Table, String> {
+    let t = table(g);
+    if t.conflicts().is_empty() { Ok(t) } else { Err(t.report()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::{LexerState,Tok};
+
+    //The part of the comment in parser.rs between from and to
+    fn parser_comment(from: &str, to: &str) -> &'static str {
+        let src = include_str!("parser.rs");
+        let start = src.find(from).unwrap() + from.len();
+        &src[start..start + src[start..].find(to).unwrap()]
+    }
+
+    //The cells of a |-separated table, each trimmed
+    fn rows(s: &str) -> Vec<Vec<&str>> {
+        s.lines().filter(|l| l.contains('|')).map(|l| l.split('|').map(|c| c.trim()).collect()).collect()
+    }
+
+    fn set(s: &str) -> BTreeSet<String> {
+        s.split(", ").filter(|t| !t.is_empty()).map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn matches_parser_comment() {
+        let g = Grammar::parse(parser_comment("RE-FACTORED GRAMMAR:", "FIRST/FOLLOW")).unwrap();
+        assert_eq!(g.productions.len(), 9);
+        let t = generate(&g).unwrap();
+        for row in rows(parser_comment("SYMBOL     FIRST       FOLLOW", "PREDICTIVE")) {
+            assert_eq!(t.sets.first[row[0]], set(row[1]), "FIRST({})", row[0]);
+            assert_eq!(t.sets.follow[row[0]], set(row[2]), "FOLLOW({})", row[0]);
+        }
+        let table = rows(parser_comment("PREDICTIVE PARSING TABLE:", "*/"));
+        let terms = &table[0];
+        for row in table[1..].iter() {
+            for (term, cell) in terms.iter().zip(row[1..].iter()) {
+                let expected: Vec<usize> = cell.split_whitespace().map(|p| p.parse().unwrap()).collect();
+                assert_eq!(t.cell(row[0], term), &expected[..], "({}, {})", row[0], term);
+            }
+        }
+        assert_eq!(t.cells.len(), 15);
+    }
+
+    #[test]
+    fn reports_conflicts() {
+        let g = Grammar::parse(parser_comment("/* GRAMMAR:", "RE-FACTORED")).unwrap();
+        let report = match generate(&g) {
+            Err(report) => report,
+            Ok(_) => panic!("the ambiguous grammar is LL(1)")
+        };
+        assert!(report.contains("<exp> on <i32>: productions 1, 2, 3"), "{}", report);
+        assert!(report.contains("<exp> on (: productions 2, 3, 4"), "{}", report);
+        assert!(report.contains("2 conflict(s)"), "{}", report);
+        let g = Grammar::parse("<s> ::= a <s> | b\n<s> ::= a").unwrap();
+        assert_eq!(table(&g).conflicts(), vec![Conflict{nonterm: "<s>".to_string(), term: "a".to_string(), productions: vec![0, 2]}]);
+        assert!(Grammar::parse("| x").is_err());
+        assert!(Grammar::parse("<a> <b> ::= x").is_err());
+    }
+
+    #[test]
+    fn drives_a_parser() {
+        let g = Grammar::parse(parser_comment("RE-FACTORED GRAMMAR:", "FIRST/FOLLOW")).unwrap();
+        let t = generate(&g).unwrap();
+        let terminals = |s: &str| -> Vec<&'static str> {
+            let mut l = LexerState::new(s);
+            let mut ts = vec![];
+            while let Ok(tok) = l.next() {
+                ts.push(match tok {
+                    Tok::I32(_) => "<i32>",
+                    Tok::LPAREN => "(",
+                    Tok::RPAREN => ")",
+                    Tok::PLUS => "+",
+                    Tok::TIMES => "*",
+                    Tok::DOLLAR => "$",
+                    tok => panic!("no terminal for {:?}", tok)
+                })
+            }
+            ts
+        };
+        assert_eq!(t.parse(&terminals("1 + 2 $")), Ok(vec![0, 1, 4, 7, 6, 2, 4, 7, 6, 3]));
+        assert_eq!(t.parse(&terminals("(1) * 2 $")).map(|d| d.len()), Ok(13));
+        assert_eq!(t.parse(&terminals("1 + * 2 $")), Err("expected <term>, found * at 2".to_string()));
+        assert_eq!(t.parse(&terminals("(1 $")), Err("expected ), found $ at 2".to_string()));
+        assert_eq!(t.parse(&terminals("1 + 2")), Err("expected <term-rest>, found end of input".to_string()));
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::VecDeque;
+
+use lexer::{lex,Context,LexError,LineInfo,Span,Tok,Trivia,Vocab};
+
+/* A lexer that, unlike LexerState, also returns the trivia between
+   tokens: whitespace, line breaks and comments, with nested block
+   comments kept whole. Every byte of the input belongs to exactly one
+   piece, so concatenating the text of the pieces gives back the input.
+   This is the starting point for tools, like pretty-printers, that have
+   to preserve what the parser ignores. */
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Piece<T = Tok> {
+    Token(T),
+    Trivia(Trivia),
+}
+
+pub struct LosslessLexer<'a, T = Tok> {
+    src: &'a str,
+    rest: &'a str,
+    info: LineInfo,
+    cx: Context,
+    //Pieces lexed but not yet returned
+    pending: VecDeque<(Piece<T>, Span)>,
+    //An error to return once pending is empty
+    error: Option<LexError>,
+    done: bool,
+}
+
+impl<'a> LosslessLexer<'a> {
+    pub fn new(s: &'a str) -> Self {
+        LosslessLexer::init(s)
+    }
+}
+
+impl<'a, T: Vocab> LosslessLexer<'a, T> {
+    //A lossless lexer for s in vocabulary T
+    pub fn init(s: &'a str) -> Self {
+        LosslessLexer{
+            src: s,
+            rest: s,
+            info: LineInfo::start(),
+            cx: Context::lossless(),
+            pending: VecDeque::new(),
+            error: None,
+            done: false,
+        }
+    }
+
+    //The input covered by span
+    pub fn text(&self, span: &Span) -> &'a str {
+        &self.src[span.start.byte_offset as usize..span.end.byte_offset as usize]
+    }
+
+    fn lex_more(&mut self) {
+        let res: Result<(T, Span), LexError> = lex(&mut self.rest, &mut self.info, &mut self.cx);
+        for (trivia, span) in self.cx.take_trivia() {
+            self.pending.push_back((Piece::Trivia(trivia), span))
+        }
+        match res {
+            Ok((tok, span)) => self.pending.push_back((Piece::Token(tok), span)),
+            //All the input was consumed
+            Err(LexError::UnexpectedEof(_)) => self.done = true,
+            Err(err) => {
+                self.error = Some(err);
+                self.done = true
+            }
+        }
+    }
+}
+
+impl<'a, T: Vocab> Iterator for LosslessLexer<'a, T> {
+    type Item = Result<(Piece<T>, Span, &'a str), LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pending.is_empty() && !self.done { self.lex_more() }
+        match self.pending.pop_front() {
+            Some((piece, span)) => {
+                let text = self.text(&span);
+                Some(Ok((piece, span, text)))
+            },
+            None => self.error.take().map(Err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asm_lexer::AsmTok;
+
+    fn concat<T: Vocab>(l: LosslessLexer<T>) -> String {
+        l.map(|res| res.unwrap().2).collect()
+    }
+
+    #[test]
+    fn round_trips() {
+        let inputs = [
+            "",
+            "  \n\n",
+            "(+ 1 /* a /* nested */ comment */ x)\r\n// trailing\r(f -3)\t$  ",
+            "/* é😀 */\t// 漢字 ü\n  1",
+            "(let x 2 // no newline at the end",
+        ];
+        for s in inputs.iter() {
+            assert_eq!(&concat(LosslessLexer::new(s)), s);
+        }
+        assert_eq!(concat(LosslessLexer::<AsmTok>::init("Lmain:\n  push 3 // x\n")), "Lmain:\n  push 3 // x\n");
+    }
+
+    #[test]
+    fn pieces() {
+        let s = "1 /* a /* b */ */\r\n// c\n+";
+        let pieces: Vec<(Piece, &str)> =
+            LosslessLexer::new(s).map(|res| res.map(|(piece, _, text)| (piece, text)).unwrap()).collect();
+        assert_eq!(pieces, vec![
+            (Piece::Token(Tok::I32(1)), "1"),
+            (Piece::Trivia(Trivia::Whitespace), " "),
+            (Piece::Trivia(Trivia::BlockComment), "/* a /* b */ */"),
+            (Piece::Trivia(Trivia::Newline), "\r\n"),
+            (Piece::Trivia(Trivia::LineComment), "// c"),
+            (Piece::Trivia(Trivia::Newline), "\n"),
+            (Piece::Token(Tok::PLUS), "+"),
+        ]);
+    }
+
+    #[test]
+    fn errors_after_trivia() {
+        let mut l = LosslessLexer::new(" /* x");
+        assert_eq!(l.next().unwrap().unwrap().0, Piece::Trivia(Trivia::Whitespace));
+        assert!(matches!(l.next(), Some(Err(LexError::UnterminatedComment(_)))));
+        assert!(l.next().is_none());
+    }
+}
@@ -1,49 +1,198 @@
+#![allow(clippy::upper_case_acronyms,clippy::to_string_trait_impl,clippy::enum_variant_names)]
 extern crate regex;
 use std::fs;
+use std::io;
 use std::env;
 
 #[allow(dead_code)]
 mod lexer;
-use lexer::{LexerState,Tok};
+use lexer::{LexError,LexerState,Tok};
 
 #[allow(dead_code)]
 mod types;
-use types::{Interp,VM};
+use types::{Exp,Interp,VM};
+
+#[allow(dead_code)]
+mod asm_lexer;
+
+#[allow(dead_code)]
+mod asm;
+
+#[allow(dead_code)]
+mod asm_parser;
+
+#[allow(dead_code)]
+mod stream_lexer;
+
+#[allow(dead_code)]
+mod lossless;
+
+#[allow(dead_code)]
+mod source_map;
+use source_map::SourceMap;
 
 #[allow(dead_code)]
 mod parser;
-use parser::{parse};
+use parser::{parse,parse_reader_each};
+
+#[allow(dead_code)]
+mod ll1;
+
+#[allow(dead_code)]
+mod ir;
+
+#[allow(dead_code)]
+mod ir_parser;
+
+#[allow(dead_code)]
+mod ir_interp;
+
+#[allow(dead_code)]
+mod typecheck;
+
+#[allow(dead_code)]
+mod source;
+
+#[allow(dead_code)]
+mod source_parser;
+
+#[allow(dead_code)]
+mod infer;
 
 #[allow(dead_code)]
 mod compile;
 use compile::{compile};
 
+mod bench;
+
+#[cfg(test)]
+mod prop;
+
+fn run(e: &Exp) {
+    println!("expression is: {}", e.to_infix());
+    match e.interp() {
+        Ok(v) => println!("result is: {}", v),
+        Err(err) => println!("evaluation error: {}", err)
+    }
+    let instrs = match compile(e) {
+        Ok(instrs) => instrs,
+        Err(err) => return println!("compile error: {}", err)
+    };
+    println!("instructions are: {:?}", instrs);
+    let mut vm = VM::init(&instrs);
+    let res = vm.run();
+    println!("VM result is: {:?}", res);
+}
+
+//Type check the GrumpyIR program in file, then run it with the
+//reference interpreter, printing what it prints and then its result.
+fn run_ir(file: &str) -> Result<(), String> {
+    let buf = fs::read_to_string(file).unwrap_or_else(|_| panic!("main: couldn't read {}", file));
+    let map = SourceMap::new(&buf);
+    let prog = match ir_parser::parse_program(&buf) {
+        Ok(prog) => prog,
+        Err(err) => {
+            eprintln!("{}", map.render(&err.span, &format!("parse error: {}", err.msg)));
+            return Err("1 parse error".to_string())
+        }
+    };
+    if let Err(errs) = typecheck::typecheck(&prog) {
+        for err in errs.iter() { eprintln!("{}", map.render(&err.span, &format!("type error: {}", err.msg))) }
+        return Err(format!("{} type error(s)", errs.len()))
+    }
+    let mut interp = ir_interp::Interpreter::new(&prog);
+    let res = interp.run();
+    let output = String::from_utf8_lossy(&interp.output);
+    print!("{}", output);
+    if !output.is_empty() && !output.ends_with('\n') { println!() }
+    match res {
+        Ok(v) => {
+            println!("result is: {:?}", v);
+            Ok(())
+        },
+        Err(err) => {
+            eprintln!("{}", map.render(&err.span, &format!("runtime error: {}", err.msg)));
+            Err("runtime error".to_string())
+        }
+    }
+}
+
+//Infer the types of the source program in file, printing its type and
+//the program with its lams annotated.
+fn run_infer(file: &str) -> Result<(), String> {
+    let buf = fs::read_to_string(file).unwrap_or_else(|_| panic!("main: couldn't read {}", file));
+    let map = SourceMap::new(&buf);
+    let e = match source_parser::parse_source(&buf) {
+        Ok(e) => e,
+        Err(err) => {
+            eprintln!("{}", map.render(&err.span, &format!("parse error: {}", err.msg)));
+            return Err("1 parse error".to_string())
+        }
+    };
+    match infer::infer(&e) {
+        Ok(inferred) => {
+            println!("type is: {}", inferred.ty.to_string());
+            println!("annotated program is: {}", inferred.exp.to_string());
+            Ok(())
+        },
+        Err(errs) => {
+            for err in errs.iter() { eprintln!("{}", map.render(&err.span, &format!("type error: {}", err.msg))) }
+            Err(format!("{} type error(s)", errs.len()))
+        }
+    }
+}
+
 fn main() -> Result<(), String> {
-    let file = env::args().last().expect("cargo run file");
-    let buf = fs::read_to_string(&file).expect(&format!("main: couldn't read {}", file));
+    let args: Vec<String> = env::args().collect();
+    if args.len() > 1 && args[1] == "--bench-lex" {
+        let terms = args.get(2).map_or(20_000, |n| n.parse().expect("bench: expected a number of terms"));
+        bench::run(terms);
+        return Ok(())
+    }
+    if args.len() > 2 && args[1] == "--ir" {
+        return run_ir(&args[2])
+    }
+    if args.len() > 2 && args[1] == "--infer" {
+        return run_infer(&args[2])
+    }
+    let file = args.last().expect("cargo run file");
+    if file == "-" {
+        //Read expressions from stdin, each ended by $, and run each as
+        //soon as it's been read
+        let stdin = io::stdin();
+        for res in parse_reader_each(stdin.lock()) {
+            match res {
+                Ok(e) => run(&e),
+                Err(errs) => for err in errs.iter() { eprintln!("parse error: {}", err) }
+            }
+        }
+        return Ok(())
+    }
+    let buf = fs::read_to_string(file).unwrap_or_else(|_| panic!("main: couldn't read {}", file));
     println!("tokens are:");
-    let mut l = LexerState::new(&buf);
+    let mut l = LexerState::infix(&buf);
     loop {
-        if let Some(tok) = l.next() {
-            println!("{:?}", tok);            
-            match tok {
-                Tok::DOLLAR => break,
-                _ => {}
+        match l.next() {
+            Ok(tok) => {
+                println!("{:?}", tok);
+                if tok == Tok::DOLLAR { break }
+            },
+            Err(LexError::UnexpectedEof(_)) => break,
+            Err(err) => {
+                eprintln!("{}", SourceMap::new(&buf).render(err.span(), &format!("lexer error: {}", err)));
+                break
             }
-        } else { break }
+        }
     }
-
     match parse(&buf) {
         Ok(e) => {
-            println!("expression is: {}", e.to_string());
-            println!("result is: {}", e.interp().to_string());
-            let instrs = compile(&e);
-            println!("instructions are: {:?}", instrs);
-            let mut vm = VM::init(&instrs);
-            let res = vm.run();
-            println!("VM result is: {:?}", res);
+            run(&e);
             Ok(())
         },
-        Err(err) => Err(err)            
+        Err(errs) => {
+            let map = SourceMap::new(&buf);
+            for err in errs.iter() { eprintln!("{}", map.render(&err.span, &format!("parse error: {}", err.msg))) }
+            Err(format!("{} parse error(s)", errs.len()))
+        }
     }
 }
@@ -1,8 +1,16 @@
-use lexer::{LexerState};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem;
+use std::io::BufRead;
+
+use lexer::{LexError,LexerState,Span,Tok,TokenStream};
+use source_map::SourceMap;
+use stream_lexer::StreamLexer;
 use lexer::Tok::*;
 use types::*;
 use types::Exp::*;
 use types::Binop::*;
+use types::Unop::*;
 
 /* GRAMMAR:
 
@@ -10,10 +18,12 @@ use types::Binop::*;
      <exp> ::== <i32>
               | <exp> + <exp>
               | <exp> * <exp>
+              | ( <exp> )
 
    RE-FACTORED GRAMMAR:
 
- 1.        <exp> ::== <term> <exp-rest> $
+ 0.      <start> ::== <exp> $
+ 1.        <exp> ::== <term> <exp-rest>
  2.   <exp-rest> ::== + <term> <exp-rest>
  3.                 | <empty-string>
  
@@ -22,99 +32,664 @@ use types::Binop::*;
  6.                 | <empty-string>
 
  7.     <factor> ::== <i32>     
+ 8.                 | ( <exp> )
 
     FIRST/FOLLOW SETS FOR REFACTORED GRAMMAR:
 
     SYMBOL     FIRST       FOLLOW
     ----------------------------
-    <exp>      | <i32>     | 
-    <exp-rest> | +         | $
-    <term>     | <i32>     | +, $
-    <term-rest>| *         | +, $
-    <factor>   | <i32>     | *
+    <start>    | <i32>, (  | 
+    <exp>      | <i32>, (  | ), $
+    <exp-rest> | +         | ), $
+    <term>     | <i32>, (  | +, ), $
+    <term-rest>| *         | +, ), $
+    <factor>   | <i32>, (  | *, +, ), $
 
     PREDICTIVE PARSING TABLE: 
  
-                 <i32>      |      +     |      *     |     $   
-    --------------------------------------------------------------
-         <exp> | 1          |            |            |   
-    <exp-rest> |            | 2          |            | 3  
-        <term> | 4          |            |            |   
-   <term-rest> |            | 6          | 5          | 6  
-      <factor> | 7          |            |            |   
-     
+                 <i32> |  (  |  )  |  +  |  *  |  $   
+    ---------------------------------------------------
+       <start> | 0     | 0   |     |     |     |   
+         <exp> | 1     | 1   |     |     |     |   
+    <exp-rest> |       |     | 3   | 2   |     | 3  
+        <term> | 4     | 4   |     |     |     |   
+   <term-rest> |       |     | 6   | 6   | 5   | 6  
+      <factor> | 7     | 8   |     |     |     |   
+
+   The LL(1) parser that implemented this table has been replaced by the
+   operator-precedence (Pratt) parser below, which covers the same
+   language plus the operators in OPS, without a <x>/<x-rest> pair of
+   nonterminals per precedence level. The sets and table above can be
+   computed from the grammar by ll1.rs, whose tests check them.
+
+   In the grammar, $ marks the end of the input. The parser accepts
+   either an explicit $ token or the end of the input there, and
+   nothing may follow. When reading a sequence of expressions, as a
+   REPL does, $ separates them (see parse_reader_each).
 */
 
-macro_rules! parse_err {
-    ( $l:expr, $err:expr ) => {
-        Err(format!("{} at {}:{} in '{}'",
-                    $err, $l.info.line_no, $l.info.col_no, $l.rest))
-    };
+/*****************************************
+ * Operator-precedence parsing
+ *****************************************/
+
+/* An operator table lists each operator's token, precedence (higher binds
+   tighter) and a function building its expression. A binary operator
+   also has an associativity: 1 - 2 - 3 is ((1 - 2) - 3) for a Left
+   operator, (1 - (2 - 3)) for a Right one, and an error for a NonAssoc
+   one. Prefix operators bind tighter than binary operators of lower
+   precedence, so - 1 * 2 is ((- 1) * 2). 
+
+   Expressions are lexed by LexerState::infix, so a - after an operand
+   is always this minus operator: 1-1 and 1 -1 are subtractions. Where
+   an operand is expected, -1 with no space is a negative literal (see
+   lexer.rs). */
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Assoc {
+    Left,
+    Right,
+    NonAssoc,
+}
+
+pub struct PrefixOp {
+    pub tok: Tok,
+    pub prec: u8,
+    pub build: fn(Exp) -> Exp,
+}
+
+pub struct InfixOp {
+    pub tok: Tok,
+    pub prec: u8,
+    pub assoc: Assoc,
+    pub build: fn(Exp, Exp) -> Exp,
+}
+
+pub struct OpTable<'a> {
+    pub prefix: &'a [PrefixOp],
+    pub infix: &'a [InfixOp],
+}
+
+fn unop(op: Unop, e: Exp) -> Exp {
+    EUnop(Box::new(Unexp{op, e}))
 }
 
-fn parse_exp(l: &mut LexerState) -> Result<Exp,String> {
-    match l.peek().expect("exp: expected a token") {
-        I32(_) => {
-            let t = parse_term(l)?;
-            let erest = parse_erest(l)?;
-            l.eat(DOLLAR);
-            Ok(EBinop(Box::new(Binexp{op: BPlus, lhs: t, rhs: erest})))
-        },
-        tok => parse_err!(l, format!("exp: unexpected token {:?}", tok))
+fn binop(op: Binop, lhs: Exp, rhs: Exp) -> Exp {
+    EBinop(Box::new(Binexp{op, lhs, rhs}))
+}
+
+//The operators of the expression language
+pub const OPS: OpTable<'static> = OpTable{
+    prefix: &[
+        PrefixOp{tok: MINUS, prec: 5, build: |e| unop(UMinus, e)},
+        PrefixOp{tok: NEG, prec: 5, build: |e| unop(UNeg, e)},
+    ],
+    infix: &[
+        InfixOp{tok: EQ, prec: 1, assoc: Assoc::NonAssoc, build: |l, r| binop(BEq, l, r)},
+        InfixOp{tok: LT, prec: 2, assoc: Assoc::NonAssoc, build: |l, r| binop(BLt, l, r)},
+        InfixOp{tok: PLUS, prec: 3, assoc: Assoc::Left, build: |l, r| binop(BPlus, l, r)},
+        InfixOp{tok: MINUS, prec: 3, assoc: Assoc::Left, build: |l, r| binop(BMinus, l, r)},
+        InfixOp{tok: TIMES, prec: 4, assoc: Assoc::Left, build: |l, r| binop(BTimes, l, r)},
+        InfixOp{tok: DIV, prec: 4, assoc: Assoc::Left, build: |l, r| binop(BDiv, l, r)},
+    ],
+};
+
+impl<'a> OpTable<'a> {
+    fn prefix_op(&self, tok: &Tok) -> Option<&'a PrefixOp> {
+        self.prefix.iter().find(|op| op.tok == *tok)
     }
+
+    fn infix_op(&self, tok: &Tok) -> Option<&'a InfixOp> {
+        self.infix.iter().find(|op| op.tok == *tok)
+    }
+}
+
+/*****************************************
+ * Error recovery
+ *****************************************/
+
+/* Rather than stopping at the first syntax error, the parser records it,
+   puts an EError node in place of the expression it was parsing, and
+   skips ahead to a token it can resume at: the ) closing the current
+   parenthesized expression, or the final $. Errors found before the
+   next token is parsed normally are likely knock-on effects of the
+   first, so they aren't recorded. Lexer errors always are. */
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct ParseError {
+    pub msg: String,
+    pub span: Span,
 }
 
-fn parse_erest(l: &mut LexerState) -> Result<Exp,String> {
-    match l.peek().expect("erest: expected a token") {
-        PLUS => {
-            l.eat(PLUS);
-            let t = parse_term(l)?;
-            let erest = parse_erest(l)?;
-            Ok(EBinop(Box::new(Binexp{op: BPlus, lhs: t, rhs: erest})))
-        },
-        DOLLAR => {
-            Ok(EI32(0)) //The unit for +
-        },
-        tok => parse_err!(l, format!("erest: unexpected token {:?}", tok))
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}:{}", self.msg, self.span.start.line_no, self.span.start.col_no)
     }
 }
 
-fn parse_term(l: &mut LexerState) -> Result<Exp,String> {
-    match l.peek().expect("term: expected a token") {
-        I32(_) => {
-            let f = parse_factor(l)?;
-            let trest = parse_trest(l)?;
-            Ok(EBinop(Box::new(Binexp{op: BTimes, lhs: f, rhs: trest})))
-        },
-        tok => parse_err!(l, format!("term: unexpected token {:?}", tok))
+impl From<LexError> for ParseError {
+    fn from(err: LexError) -> ParseError {
+        ParseError{msg: err.message(), span: err.span().clone()}
     }
 }
 
-fn parse_trest(l: &mut LexerState) -> Result<Exp,String> {
-    match l.peek().expect("trest: expected a token") {
-        TIMES => {
-            l.eat(TIMES);
-            let f = parse_factor(l)?;
-            let trest = parse_trest(l)?;
-            Ok(EBinop(Box::new(Binexp{op: BTimes, lhs: f, rhs: trest})))
-        },
-        PLUS => Ok(EI32(1)), //The unit for *
-        DOLLAR => Ok(EI32(1)), //The unit for *
-        tok => parse_err!(l, format!("erest: unexpected token {:?}", tok))
+//Describe a token peeked by the parser, None meaning end of input.
+fn describe(tok: &Option<Tok>) -> String {
+    match tok {
+        Some(tok) => format!("{:?}", tok),
+        None => "end of program".to_string()
     }
 }
 
-fn parse_factor(l: &mut LexerState) -> Result<Exp,String> {
-    match l.peek().expect("factor: expected a token") {
-        I32(i) => {
-            l.eat(I32(i));
-            Ok(EI32(i))
-        },
-        tok => parse_err!(l, format!("term: unexpected token {:?}", tok))
+struct Parser<'o, L> {
+    l: L,
+    ops: &'o OpTable<'o>,
+    errors: Vec<ParseError>,
+    //Has an error been found since the last token parsed?
+    panicking: bool,
+    //Number of enclosing parentheses
+    depth: usize,
+    //The end of the input, once reached
+    eof: Option<Span>,
+    //Groups that can be reused from a previous parse (see Tree)
+    reuse: Option<Reuse<'o>>,
+    //Groups parsed without errors, if they're being recorded
+    groups: Option<BTreeMap<u64, Group>>,
+}
+
+impl<'o, L: TokenStream> Parser<'o, L> {
+    fn new(l: L, ops: &'o OpTable<'o>) -> Self {
+        Parser{l, ops, errors: vec![], panicking: false, depth: 0, eof: None, reuse: None, groups: None}
+    }
+
+    //The next token and its span, recording and skipping lexer errors
+    fn peek(&mut self) -> (Option<Tok>, Span) {
+        loop {
+            if let Some(ref span) = self.eof { return (None, span.clone()) }
+            match self.l.peek_spanned() {
+                Ok((tok, span)) => return (Some(tok), span),
+                Err(LexError::UnexpectedEof(span)) => self.eof = Some(span),
+                Err(err) => {
+                    self.errors.push(ParseError::from(err.clone()));
+                    if let LexError::ReadError(_, ref span) = err {
+                        //The rest of the input can't be read
+                        self.eof = Some(span.clone())
+                    } else {
+                        let _ = self.l.next_spanned();
+                    }
+                }
+            }
+        }
+    }
+
+    //Consume the token just peeked.
+    fn advance(&mut self) {
+        let _ = self.l.next_spanned();
+        self.panicking = false
+    }
+
+    fn error(&mut self, msg: String, span: Span) {
+        if !self.panicking {
+            self.errors.push(ParseError{msg, span});
+            self.panicking = true
+        }
+    }
+
+    //Skip to the next $, or the next ) if inside parentheses, ignoring
+    //parenthesized groups along the way.
+    fn sync(&mut self) {
+        let mut nested = 0;
+        loop {
+            match self.peek().0 {
+                None | Some(DOLLAR) => return,
+                Some(RPAREN) if nested == 0 && self.depth > 0 => return,
+                Some(RPAREN) => nested = usize::saturating_sub(nested, 1),
+                Some(LPAREN) => nested += 1,
+                Some(_) => {}
+            }
+            let _ = self.l.next_spanned();
+        }
+    }
+
+    //Consume expected, or record an error and skip past where it should be.
+    fn expect(&mut self, expected: Tok) {
+        let (tok, span) = self.peek();
+        if tok.as_ref() != Some(&expected) {
+            self.error(format!("expected {:?}, found {}", expected, describe(&tok)), span);
+            self.sync();
+            if self.peek().0.as_ref() != Some(&expected) { return }
+        }
+        self.advance()
+    }
+
+    fn parse_start(&mut self) -> Exp {
+        let e = self.parse_exp(0);
+        self.parse_end();
+        //Nothing may follow the end of the expression
+        let (tok, span) = self.peek();
+        if let Some(tok) = tok {
+            self.error(format!("unexpected {:?} after the end of the expression", tok), span);
+            while self.peek().0.is_some() { let _ = self.l.next_spanned(); }
+        }
+        e
+    }
+
+    //Parse the end of an expression: a $ or the end of the input.
+    fn parse_end(&mut self) {
+        let (tok, span) = self.peek();
+        match tok {
+            Some(DOLLAR) => self.advance(),
+            None => {},
+            Some(tok) => {
+                self.error(format!("expected an operator, $ or end of program, found {:?}", tok), span);
+                self.sync();
+                if self.peek().0 == Some(DOLLAR) { self.advance() }
+            }
+        }
+    }
+
+    //Parse an expression whose binary operators (outside parentheses) all
+    //have precedence at least min_prec.
+    fn parse_exp(&mut self, min_prec: u8) -> Exp {
+        let ops = self.ops;
+        let mut lhs = self.parse_prefix();
+        loop {
+            let op = match self.peek().0.and_then(|tok| ops.infix_op(&tok)) {
+                Some(op) if op.prec >= min_prec => op,
+                _ => return lhs
+            };
+            self.advance();
+            let rhs_prec = if op.assoc == Assoc::Right { op.prec } else { op.prec + 1 };
+            let rhs = self.parse_exp(rhs_prec);
+            lhs = (op.build)(lhs, rhs);
+            if op.assoc == Assoc::NonAssoc {
+                let (tok, span) = self.peek();
+                if tok.as_ref().and_then(|tok| ops.infix_op(tok)).is_some_and(|next| next.prec == op.prec) {
+                    self.error(format!("{} after {:?} needs parentheses", describe(&tok), op.tok), span)
+                }
+            }
+        }
+    }
+
+    //Parse a literal, a parenthesized expression or a prefix operator
+    //applied to an expression.
+    fn parse_prefix(&mut self) -> Exp {
+        let ops = self.ops;
+        let (tok, span) = self.peek();
+        match tok {
+            Some(I32(i)) => {
+                self.advance();
+                EI32(i)
+            },
+            Some(LPAREN) => {
+                if let Some(e) = self.reuse_group(&span) { return e }
+                let errors = self.errors.len();
+                self.advance();
+                self.depth += 1;
+                let e = self.parse_exp(0);
+                self.expect(RPAREN);
+                self.depth -= 1;
+                if let Some(ref mut groups) = self.groups {
+                    if self.errors.len() == errors {
+                        groups.insert(span.start.byte_offset, Group{end: self.l.info().byte_offset, exp: e.clone()});
+                    }
+                }
+                e
+            },
+            Some(ref tok) if ops.prefix_op(tok).is_some() => {
+                let op = ops.prefix_op(tok).unwrap();
+                self.advance();
+                let e = self.parse_exp(op.prec);
+                (op.build)(e)
+            },
+            tok => {
+                self.error(format!("expected an expression, found {}", describe(&tok)), span);
+                self.sync();
+                EError
+            }
+        }
     }
+
+    //If the group opened at open is unchanged from the previous parse,
+    //skip past it and return its expression.
+    fn reuse_group(&mut self, open: &Span) -> Option<Exp> {
+        let reuse = self.reuse.as_mut()?;
+        let edit = reuse.edit;
+        let start = edit.old_offset(open.start.byte_offset)?;
+        let group = reuse.old.get(&start)?;
+        if start < edit.end && group.end > edit.start { return None }
+        if !self.l.skip_to(reuse.map.info_at(edit.new_offset(start, group.end))) { return None }
+        self.panicking = false;
+        if let Some(ref mut groups) = self.groups {
+            for (&inner_start, inner) in reuse.old.range(start..group.end) {
+                let end = edit.new_offset(start, inner.end);
+                groups.insert(edit.new_offset(start, inner_start), Group{end, exp: inner.exp.clone()});
+            }
+        }
+        reuse.count += 1;
+        Some(group.exp.clone())
+    }
+}
+
+fn finish((e, errors): (Exp, Vec<ParseError>)) -> Result<Exp,Vec<ParseError>> {
+    if errors.is_empty() { Ok(e) } else { Err(errors) }
+}
+
+//Parse s using the operators in ops, recovering from syntax errors.
+//Returns the expression, with EError in place of the parts that
+//couldn't be parsed, and every error found.
+pub fn parse_partial(s: &str, ops: &OpTable) -> (Exp, Vec<ParseError>) {
+    let mut p = Parser::new(LexerState::infix(s), ops);
+    let e = p.parse_start();
+    (e, p.errors)
+}
+
+//Parse s using the operators in ops.
+pub fn parse_with(s: &str, ops: &OpTable) -> Result<Exp,Vec<ParseError>> {
+    finish(parse_partial(s, ops))
+}
+
+pub fn parse(s: &str) -> Result<Exp,Vec<ParseError>> {
+    parse_with(s, &OPS)
+}
+
+//Parse a program read incrementally from r.
+pub fn parse_reader<R: BufRead>(r: R) -> Result<Exp,Vec<ParseError>> {
+    let mut p = Parser::new(StreamLexer::infix(r), &OPS);
+    let e = p.parse_start();
+    finish((e, p.errors))
+}
+
+//The expressions in a sequence, each ended by $ or the end of the input
+pub struct Exps<'o, L> {
+    p: Parser<'o, L>,
 }
 
-pub fn parse(s: &str) -> Result<Exp,String> {
-    let mut l = LexerState::new(s);
-    parse_exp(&mut l)
+impl<'o, L: TokenStream> Iterator for Exps<'o, L> {
+    type Item = Result<Exp,Vec<ParseError>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.p.panicking = false;
+        if self.p.peek().0.is_none() {
+            //Report any lexer errors in trivia at the end of the input
+            if self.p.errors.is_empty() { return None }
+            return Some(Err(mem::take(&mut self.p.errors)))
+        }
+        let e = self.p.parse_exp(0);
+        self.p.parse_end();
+        Some(finish((e, mem::take(&mut self.p.errors))))
+    }
+}
+
+//Parse expressions read incrementally from r, each ended by $ or the end
+//of the input. Each is returned as soon as its $ has been read, so this
+//suits interactive input.
+pub fn parse_reader_each<'o, R: BufRead>(r: R) -> Exps<'o, StreamLexer<R>> {
+    Exps{p: Parser::new(StreamLexer::infix(r), &OPS)}
+}
+
+/*****************************************
+ * Incremental reparsing
+ *****************************************/
+
+/* A Tree is a parse kept so the text can be reparsed cheaply after each
+   edit, as in an editor. Besides the expression, it records every
+   parenthesized group that parsed without errors, keyed by the byte
+   offset of its (. Such a group parses the same way wherever it
+   appears: the lexer is between tokens at the (, since it reached it,
+   and the parser's state outside the parentheses doesn't matter inside
+   them. So on reparsing, a ( outside the edited text that opened a
+   group before the edit opens the same group after it, and the parser
+   takes the old group's expression and skips the lexer to just past
+   its ), instead of parsing it again. */
+
+#[derive(Debug,Clone,PartialEq)]
+struct Group {
+    //The offset just past the )
+    end: u64,
+    exp: Exp,
+}
+
+#[derive(Debug,Clone)]
+pub struct Tree {
+    pub src: String,
+    pub exp: Exp,
+    pub errors: Vec<ParseError>,
+    //The number of groups reused from the previous tree, if reparsed
+    pub reused: usize,
+    groups: BTreeMap<u64, Group>,
+}
+
+//Replace the bytes start..end of the text with text
+#[derive(Debug,Clone,PartialEq)]
+pub struct TextEdit {
+    pub start: u64,
+    pub end: u64,
+    pub text: String,
+}
+
+impl TextEdit {
+    //The offset before the edit of the byte at offset new after it, or
+    //None if the edit inserted that byte
+    fn old_offset(&self, new: u64) -> Option<u64> {
+        let len = self.text.len() as u64;
+        if new < self.start { Some(new) }
+        else if new >= self.start + len { Some(new - len + self.end - self.start) }
+        else { None }
+    }
+
+    //The offset after the edit of offset old before it, in a group
+    //opened at offset group that the edit didn't touch. An insertion at
+    //a group's ( goes before it.
+    fn new_offset(&self, group: u64, old: u64) -> u64 {
+        if group < self.end { old } else { old - self.end + self.start + self.text.len() as u64 }
+    }
+}
+
+struct Reuse<'a> {
+    edit: &'a TextEdit,
+    old: BTreeMap<u64, Group>,
+    map: SourceMap<'a>,
+    count: usize,
+}
+
+//Parse src into a tree, reusing groups from reuse if given.
+fn parse_tree_from(src: String, reuse: Option<(&TextEdit, BTreeMap<u64, Group>)>) -> Tree {
+    let (exp, errors, groups, reused) = {
+        let mut p = Parser::new(LexerState::infix(&src), &OPS);
+        p.reuse = reuse.map(|(edit, old)| Reuse{edit, old, map: SourceMap::new(&src), count: 0});
+        p.groups = Some(BTreeMap::new());
+        let exp = p.parse_start();
+        (exp, p.errors, p.groups.unwrap_or_default(), p.reuse.map_or(0, |r| r.count))
+    };
+    Tree{src, exp, errors, reused, groups}
+}
+
+//Parse s, recovering from errors, into a tree that can be reparsed.
+pub fn parse_tree(s: &str) -> Tree {
+    parse_tree_from(s.to_string(), None)
+}
+
+//Apply edit to old's text and reparse it, reusing the groups the edit
+//didn't touch. The result is the same as parse_tree on the new text.
+//Panics if the edit's offsets aren't character boundaries in the text.
+pub fn parse_incremental(old: Tree, edit: &TextEdit) -> Tree {
+    let mut src = old.src;
+    src.replace_range(edit.start as usize..edit.end as usize, &edit.text);
+    parse_tree_from(src, Some((edit, old.groups)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn i(n: i32) -> Exp { EI32(n) }
+
+    #[test]
+    fn left_associative() {
+        assert_eq!(parse("1 $"), Ok(i(1)));
+        assert_eq!(parse("1 + 2 + 3 $"), Ok(binop(BPlus, binop(BPlus, i(1), i(2)), i(3))));
+        assert_eq!(parse("1 * 2 + 3 * 4 * 5 $"),
+                   Ok(binop(BPlus, binop(BTimes, i(1), i(2)), binop(BTimes, binop(BTimes, i(3), i(4)), i(5)))));
+        assert_eq!(parse("2 * (3 + -4) $"), Ok(binop(BTimes, i(2), binop(BPlus, i(3), i(-4)))));
+        assert_eq!(parse("8 - 4 - 2 / 2 $"), Ok(binop(BMinus, binop(BMinus, i(8), i(4)), binop(BDiv, i(2), i(2)))));
+    }
+
+    #[test]
+    fn prefix_and_comparisons() {
+        assert_eq!(parse("- 1 * 2 $"), Ok(binop(BTimes, unop(UMinus, i(1)), i(2))));
+        assert_eq!(parse("- - 1 $"), Ok(unop(UMinus, unop(UMinus, i(1)))));
+        assert_eq!(parse("neg 1 + 2 < 3 == 0 $"),
+                   Ok(binop(BEq, binop(BLt, binop(BPlus, unop(UNeg, i(1)), i(2)), i(3)), i(0))));
+        assert_eq!(parse("neg (1 == 2) $"), Ok(unop(UNeg, binop(BEq, i(1), i(2)))));
+        assert!(parse("1 < 2 < 3 $").is_err());
+        assert!(parse("1 == 2 == 3 $").is_err());
+        assert!(parse("(1 < 2) < 3 $").is_ok());
+    }
+
+    #[test]
+    fn minus_after_an_operand() {
+        let sub = |l, r| Ok(binop(BMinus, l, r));
+        assert_eq!(parse("1-1 $"), sub(i(1), i(1)));
+        assert_eq!(parse("1 -1 $"), sub(i(1), i(1)));
+        assert_eq!(parse("(2)-3 $"), sub(i(2), i(3)));
+        assert_eq!(parse("1 - -1 $"), sub(i(1), i(-1)));
+        assert_eq!(parse("(-1)-2*3 $"), sub(i(-1), binop(BTimes, i(2), i(3))));
+        assert_eq!(parse("1-2147483647 $"), sub(i(1), i(i32::MAX)));
+        assert_eq!(parse_reader("4-3-2 $".as_bytes()), sub(binop(BMinus, i(4), i(3)), i(2)));
+    }
+
+    #[test]
+    fn custom_table() {
+        //Right-associative - with a lower precedence than +
+        let ops = OpTable{
+            prefix: &[],
+            infix: &[
+                InfixOp{tok: MINUS, prec: 1, assoc: Assoc::Right, build: |l, r| binop(BMinus, l, r)},
+                InfixOp{tok: PLUS, prec: 2, assoc: Assoc::Left, build: |l, r| binop(BPlus, l, r)},
+            ],
+        };
+        assert_eq!(parse_with("1 - 2 + 3 - 4 $", &ops),
+                   Ok(binop(BMinus, i(1), binop(BMinus, binop(BPlus, i(2), i(3)), i(4)))));
+        assert!(parse_with("- 1 $", &ops).is_err());
+        assert!(parse_with("1 * 2 $", &ops).is_err());
+    }
+
+    #[test]
+    fn infix_round_trips() {
+        for s in ["7", "1 + 2 * 3 + 4", "(1 + 2) * (3 * -4 + 5)", "((((1))))",
+                  "- (1 - 2) / neg 3", "1 < 2 == (3 == 4)"].iter() {
+            let e = parse(&format!("{} $", s)).unwrap();
+            assert_eq!(parse(&format!("{} $", e.to_infix())), Ok(e));
+        }
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse("1 + $").is_err());
+        assert!(parse("(1 + 2 $").is_err());
+        assert!(parse("1 + 2) $").is_err());
+        assert!(parse("1 2 $").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn optional_end_marker() {
+        assert_eq!(parse("1 + 2"), Ok(binop(BPlus, i(1), i(2))));
+        assert_eq!(parse("1 + 2 /* done */\n"), parse("1 + 2 $"));
+        assert_eq!(errors_of("1 + 2 $ 3"), vec![("unexpected I32(3) after the end of the expression".to_string(), 1, 8)]);
+        assert_eq!(errors_of("1 + 2 $ $"), vec![("unexpected DOLLAR after the end of the expression".to_string(), 1, 8)]);
+        assert_eq!(errors_of("1 + 2 3 4"), vec![("expected an operator, $ or end of program, found I32(3)".to_string(), 1, 6)]);
+        assert_eq!(errors_of("(1 + 2"), vec![("expected RPAREN, found end of program".to_string(), 1, 6)]);
+        assert_eq!(errors_of("1 +"), vec![("expected an expression, found end of program".to_string(), 1, 3)]);
+    }
+
+    #[test]
+    fn expression_sequences() {
+        let input = "1 + 2 $\n3 * (4 $ 5 6 $\n7";
+        let results: Vec<Result<Exp,Vec<ParseError>>> = parse_reader_each(input.as_bytes()).collect();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0], Ok(binop(BPlus, i(1), i(2))));
+        assert_eq!(results[1].clone().unwrap_err()[0].msg, "expected RPAREN, found DOLLAR");
+        assert_eq!(results[2].clone().unwrap_err()[0].msg, "expected an operator, $ or end of program, found I32(6)");
+        assert_eq!(results[3], Ok(i(7)));
+        assert_eq!(parse_reader_each("  // nothing\n".as_bytes()).count(), 0);
+        assert_eq!(parse_reader_each("1 /* ".as_bytes()).count(), 1);
+    }
+
+    fn errors_of(s: &str) -> Vec<(String, u64, u64)> {
+        parse_partial(s, &OPS).1.into_iter()
+            .map(|err| (err.msg, err.span.start.line_no, err.span.start.col_no)).collect()
+    }
+
+    #[test]
+    fn recovers_from_errors() {
+        let (e, errors) = parse_partial("(1 + ) * (2 3) + 4 $", &OPS);
+        assert_eq!(e, binop(BPlus, binop(BTimes, binop(BPlus, i(1), EError), i(2)), i(4)));
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors_of("(1 + ) * (2 3) + 4 $"), vec![
+            ("expected an expression, found RPAREN".to_string(), 1, 5),
+            ("expected RPAREN, found I32(3)".to_string(), 1, 12),
+        ]);
+        //One error for one mistake
+        assert_eq!(errors_of("(1 + $"), vec![("expected an expression, found DOLLAR".to_string(), 1, 5)]);
+        assert_eq!(errors_of("1 + 2) $").len(), 1);
+        assert_eq!(errors_of("1\n+ * ((2) 3) + 4 $"), vec![("expected an expression, found TIMES".to_string(), 2, 2)]);
+        assert_eq!(errors_of("1 +"), vec![("expected an expression, found end of program".to_string(), 1, 3)]);
+    }
+
+    #[test]
+    fn reports_lexer_errors() {
+        let (e, errors) = parse_partial("1 + ? 2 * 99999999999 */ $", &OPS);
+        assert_eq!(e, binop(BPlus, i(1), binop(BTimes, i(2), EError)));
+        assert_eq!(errors.iter().map(|err| err.msg.as_str()).collect::<Vec<_>>(), vec![
+            "unexpected character '?'",
+            "integer literal 99999999999 out of range",
+            "'*/' outside of a comment",
+            "expected an expression, found DOLLAR",
+        ]);
+    }
+
+    fn edit(start: u64, end: u64, text: &str) -> TextEdit {
+        TextEdit{start, end, text: text.to_string()}
+    }
+
+    #[test]
+    fn reparses_incrementally() {
+        let tree = parse_tree("(1 + (2)) * (3 - 4)\n/ (5) $");
+        assert!(tree.errors.is_empty());
+        //Replace 3 with 30: only the group around it is reparsed
+        let tree = parse_incremental(tree, &edit(13, 14, "30"));
+        assert_eq!(tree.src, "(1 + (2)) * (30 - 4)\n/ (5) $");
+        assert_eq!(tree.reused, 2);
+        assert_eq!(tree.exp, parse(&tree.src).unwrap());
+        //An edit that breaks the text, then one that mends it
+        let tree = parse_incremental(tree, &edit(0, 0, "/*"));
+        assert_eq!(tree.errors[0].msg, "unterminated comment");
+        let tree = parse_incremental(tree, &edit(0, 2, "neg "));
+        assert!(tree.errors.is_empty());
+        assert_eq!(tree.exp, parse(&tree.src).unwrap());
+    }
+
+    #[test]
+    fn incremental_agrees_with_full_reparse() {
+        use prop::{gen_exp,Rng};
+        const TEXTS: [&str; 16] = ["", "1", "-7", " ", "\n", "+", "-", "*", "neg ", "<", "(", ")", "(8 / 2)", "/*", "*/", "$"];
+        let mut rng = Rng::new(19);
+        let mut reused = 0;
+        for _ in 0..100 {
+            let mut tree = parse_tree(&gen_exp(&mut rng, 5).to_infix());
+            for _ in 0..20 {
+                let len = tree.src.len() as u64;
+                let start = rng.below(len + 1);
+                let end = (start + rng.below(4)).min(len);
+                let e = edit(start, end, rng.choose::<&str>(&TEXTS));
+                tree = parse_incremental(tree, &e);
+                reused += tree.reused;
+                let full = parse_tree(&tree.src);
+                assert_eq!((&tree.exp, &tree.errors), (&full.exp, &full.errors), "after {:?}", e);
+                assert_eq!(tree.groups, full.groups, "after {:?}", e);
+            }
+        }
+        assert!(reused > 1000, "only {} groups reused", reused);
+    }
 }
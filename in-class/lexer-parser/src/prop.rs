@@ -0,0 +1,274 @@
+use std::env;
+
+use compile::compile;
+use asm::Val;
+use ir_interp;
+use ir_parser::parse_program;
+use typecheck::typecheck;
+use lossless::{LosslessLexer,Piece};
+use parser::parse;
+use types::*;
+use types::Binop::*;
+use types::Exp::*;
+use types::Unop::*;
+
+/* Property-based tests. Each property is checked on CASES random
+   expressions; a failing expression is shrunk to a minimal one that
+   still fails before it's reported. Cases are derived from a fixed
+   seed, so failures reproduce, and PROP_SEED=n tries a different set. */
+
+const CASES: u64 = 500;
+const MAX_DEPTH: u32 = 6;
+
+//xorshift64*
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    //A number in 0..n
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    pub fn choose<'a, T>(&mut self, xs: &'a [T]) -> &'a T {
+        &xs[self.below(xs.len() as u64) as usize]
+    }
+}
+
+fn base_seed() -> u64 {
+    env::var("PROP_SEED").ok().and_then(|s| s.parse().ok()).unwrap_or(4100)
+}
+
+/********************************************
+ * Generating and shrinking expressions
+ ********************************************/
+
+const BINOPS: [Binop; 6] = [BPlus, BTimes, BMinus, BDiv, BLt, BEq];
+const UNOPS: [Unop; 2] = [UMinus, UNeg];
+
+pub fn gen_i32(rng: &mut Rng) -> i32 {
+    match rng.below(10) {
+        0 => *rng.choose(&[i32::MIN, i32::MAX, -1, 0, 1]),
+        1 => rng.next_u64() as i32,
+        _ => rng.below(41) as i32 - 20
+    }
+}
+
+//A random expression at most depth deep
+pub fn gen_exp(rng: &mut Rng, depth: u32) -> Exp {
+    if depth == 0 || rng.below(4) == 0 { return EI32(gen_i32(rng)) }
+    if rng.below(5) == 0 {
+        let op = rng.choose(&UNOPS).clone();
+        EUnop(Box::new(Unexp{op, e: gen_exp(rng, depth - 1)}))
+    } else {
+        let op = rng.choose(&BINOPS).clone();
+        let lhs = gen_exp(rng, depth - 1);
+        EBinop(Box::new(Binexp{op, lhs, rhs: gen_exp(rng, depth - 1)}))
+    }
+}
+
+//Expressions smaller than e, most promising first
+pub fn shrink(e: &Exp) -> Vec<Exp> {
+    match e {
+        EI32(0) | EError => vec![],
+        EI32(i) => if *i / 2 == 0 { vec![EI32(0)] } else { vec![EI32(0), EI32(*i / 2)] },
+        EUnop(u) => {
+            let mut es = vec![u.e.clone()];
+            es.extend(shrink(&u.e).into_iter().map(|e| EUnop(Box::new(Unexp{op: u.op.clone(), e}))));
+            es
+        },
+        EBinop(b) => {
+            let mut es = vec![b.lhs.clone(), b.rhs.clone()];
+            es.extend(shrink(&b.lhs).into_iter().map(|lhs| EBinop(Box::new(Binexp{lhs, ..(**b).clone()}))));
+            es.extend(shrink(&b.rhs).into_iter().map(|rhs| EBinop(Box::new(Binexp{rhs, ..(**b).clone()}))));
+            es
+        },
+        //gen_exp makes only the forms above
+        _ => vec![]
+    }
+}
+
+//Shrink e as long as it still fails.
+pub fn minimize<F: Fn(&Exp) -> bool>(e: Exp, fails: F) -> Exp {
+    let mut e = e;
+    while let Some(smaller) = shrink(&e).into_iter().find(|e| fails(e)) { e = smaller }
+    e
+}
+
+//Check prop on random expressions, panicking with a minimal
+//counterexample if it fails. prop is passed a generator seeded by the
+//case, so it can make further random choices reproducibly.
+pub fn check<P: Fn(&Exp, &mut Rng) -> Result<(), String>>(name: &str, prop: P) {
+    let seed = base_seed();
+    for case in 0..CASES {
+        let case_seed = seed.wrapping_add(case);
+        let e = gen_exp(&mut Rng::new(case_seed), MAX_DEPTH);
+        let prop_rng = || Rng::new(!case_seed);
+        if prop(&e, &mut prop_rng()).is_err() {
+            let min = minimize(e, |e| prop(e, &mut prop_rng()).is_err());
+            let msg = prop(&min, &mut prop_rng()).unwrap_err();
+            panic!("{} failed (PROP_SEED={}, case {}) for {}: {}", name, seed, case, min.to_infix(), msg)
+        }
+    }
+}
+
+/********************************************
+ * Properties
+ ********************************************/
+
+//The value of e, or None if evaluating it overflows or divides by zero.
+//A reference for Interp::interp in Trap mode.
+fn checked_interp(e: &Exp) -> Option<i32> {
+    match e {
+        EI32(i) => Some(*i),
+        EUnop(u) => {
+            let v = checked_interp(&u.e)?;
+            match u.op {
+                UMinus => v.checked_neg(),
+                UNeg => Some((v == 0) as i32)
+            }
+        },
+        EBinop(b) => {
+            let (v1, v2) = (checked_interp(&b.lhs)?, checked_interp(&b.rhs)?);
+            match b.op {
+                BPlus => v1.checked_add(v2),
+                BTimes => v1.checked_mul(v2),
+                BMinus => v1.checked_sub(v2),
+                BDiv => v1.checked_div(v2),
+                BLt => Some((v1 < v2) as i32),
+                BEq => Some((v1 == v2) as i32)
+            }
+        },
+        EBool(b) => Some(*b as i32),
+        //EError and the forms the interpreter doesn't support
+        _ => None
+    }
+}
+
+const TRIVIA: [&str; 8] = [" ", "\n", "\t", "\r\n", " /* c */ ", " /* a /* nested */ comment */ ",
+                           " // line comment\n", " /**/ "];
+
+//s with whitespace and comments inserted at random token boundaries.
+//Comments are surrounded by whitespace so they can't join up with an
+//adjacent / or *.
+fn add_trivia(s: &str, rng: &mut Rng) -> String {
+    let mut out = String::new();
+    for piece in LosslessLexer::new(s) {
+        let (piece, _, text) = piece.expect("add_trivia: lexer error");
+        out.push_str(text);
+        if let Piece::Token(_) = piece {
+            while rng.below(3) == 0 { out.push_str(rng.choose::<&str>(&TRIVIA)) }
+        }
+    }
+    out
+}
+
+fn parses_to(s: &str, e: &Exp) -> Result<(), String> {
+    match parse(s) {
+        Ok(ref parsed) if parsed == e => Ok(()),
+        Ok(parsed) => Err(format!("{:?} parsed as {}", s, parsed.to_infix())),
+        Err(errs) => Err(format!("{:?} didn't parse: {}", s, errs[0]))
+    }
+}
+
+#[test]
+fn infix_round_trips() {
+    check("infix_round_trips", |e, _| {
+        parses_to(&e.to_infix(), e)?;
+        parses_to(&format!("{} $", e.to_infix()), e)
+    })
+}
+
+#[test]
+fn round_trips_with_trivia() {
+    check("round_trips_with_trivia", |e, rng| parses_to(&add_trivia(&format!("{} $", e.to_infix()), rng), e))
+}
+
+//Printing as GrumpyIR and parsing that back gives an expression that
+//prints the same and has the same value. (It isn't always e itself:
+//- e is written (- 0 e).)
+#[test]
+fn to_string_round_trips() {
+    check("to_string_round_trips", |e, _| {
+        let s = e.to_string();
+        let parsed = match parse_program(&format!("% {}", s)) {
+            Ok(prog) => prog.main.lower(),
+            Err(err) => return Err(format!("{:?} didn't parse: {}", s, err))
+        };
+        if parsed.to_string() != s { return Err(format!("{:?} parsed as {}", s, parsed.to_string())) }
+        if parsed.interp() != e.interp() { return Err(format!("{:?} parsed as {:?}", s, parsed)) }
+        Ok(())
+    })
+}
+
+#[test]
+fn vm_agrees_with_interp() {
+    check("vm_agrees_with_interp", |e, _| {
+        let expected = checked_interp(e);
+        if e.interp().ok() != expected {
+            return Err(format!("expected {:?}, interp gave {:?}", expected, e.interp()))
+        }
+        let instrs = compile(e).map_err(|err| format!("didn't compile: {}", err))?;
+        for mode in [OverflowMode::Trap, OverflowMode::Wrap, OverflowMode::Saturate].iter() {
+            let (interp, vm) = (e.interp_with(*mode), VM::with_mode(&instrs, *mode).run());
+            if interp != vm { return Err(format!("in {:?} mode, interp gave {:?}, VM gave {:?}", mode, interp, vm)) }
+        }
+        Ok(())
+    })
+}
+
+//The reference interpreter for GrumpyIR and the VM agree where the
+//expression is well-typed GrumpyIR. (Booleans are 0 and 1 in the VM,
+//and neg and arithmetic on them are GrumpyIR type errors.) On a
+//well-typed expression, the only errors the interpreter may give are
+//overflow and division by zero.
+#[test]
+fn ir_interp_agrees_with_vm() {
+    check("ir_interp_agrees_with_vm", |e, _| {
+        let prog = parse_program(&format!("% {}", e.to_string())).map_err(|err| err.to_string())?;
+        if typecheck(&prog).is_err() { return Ok(()) }
+        let vm = VM::init(&compile(e).map_err(|err| format!("didn't compile: {}", err))?).run();
+        let expected = match ir_interp::run(&prog) {
+            Ok((Val::Vi32(i), _)) => Ok(i),
+            Ok((Val::Vbool(b), _)) => Ok(b as i32),
+            Ok((v, _)) => return Err(format!("unexpected value {:?}", v)),
+            Err(err) if err.msg.starts_with("arithmetic overflow") || err.msg == "division by zero" => {
+                if vm.is_err() { return Ok(()) }
+                return Err(format!("interpreter gave {}, VM gave {:?}", err.msg, vm))
+            },
+            Err(err) => return Err(format!("interpreter gave {}", err))
+        };
+        if vm == expected { Ok(()) } else { Err(format!("interpreter gave {:?}, VM gave {:?}", expected, vm)) }
+    })
+}
+
+#[test]
+fn shrinks_to_minimal_case() {
+    //Fails on any expression containing a division by a nonzero literal
+    fn divides(e: &Exp) -> bool {
+        match e {
+            EUnop(u) => divides(&u.e),
+            EBinop(b) => (b.op == BDiv && b.rhs != EI32(0) && matches!(b.rhs, EI32(_)))
+                || divides(&b.lhs) || divides(&b.rhs),
+            _ => false
+        }
+    }
+    let mut rng = Rng::new(base_seed());
+    let e = loop {
+        let e = gen_exp(&mut rng, MAX_DEPTH);
+        if divides(&e) { break e }
+    };
+    let min = minimize(e, divides);
+    assert!(matches!(min, EBinop(ref b) if b.lhs == EI32(0) && (b.rhs == EI32(1) || b.rhs == EI32(-1))),
+            "{}", min.to_string());
+}
@@ -0,0 +1,84 @@
+use lexer::Span;
+use types::{Binop,Unop};
+
+/********************************************
+ * Grumpy source language abstract syntax
+ ********************************************/
+
+/* The syntax of the source language described in doc/source.md. It
+   extends GrumpyIR's expressions with anonymous functions (lam) and
+   their application (app), and has no top-level function definitions.
+   The type annotation on a lam may be left out, (lam x e), in which
+   case infer.rs works it out. */
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum SourceTy {
+    I32,
+    Bool,
+    Unit,
+    Array(Box<SourceTy>),
+    //(-> ty1 ty2)
+    Fun(Box<SourceTy>, Box<SourceTy>),
+}
+
+impl ToString for SourceTy {
+    fn to_string(&self) -> String {
+        match self {
+            SourceTy::I32 => "i32".to_string(),
+            SourceTy::Bool => "bool".to_string(),
+            SourceTy::Unit => "unit".to_string(),
+            SourceTy::Array(t) => format!("(array {})", t.to_string()),
+            SourceTy::Fun(t1, t2) => format!("(-> {} {})", t1.to_string(), t2.to_string())
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct SourceExp {
+    pub kind: SourceKind,
+    pub span: Span,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum SourceKind {
+    I32(i32),
+    Bool(bool),
+    Unit,
+    Var(String),
+    Unop(Unop, Box<SourceExp>),
+    Binop(Binop, Box<SourceExp>, Box<SourceExp>),
+    Let(String, Box<SourceExp>, Box<SourceExp>),
+    Seq(Box<SourceExp>, Box<SourceExp>),
+    Alloc(Box<SourceExp>, Box<SourceExp>),
+    Set(Box<SourceExp>, Box<SourceExp>, Box<SourceExp>),
+    Get(Box<SourceExp>, Box<SourceExp>),
+    Cond(Box<SourceExp>, Box<SourceExp>, Box<SourceExp>),
+    //(lam x ty e), or (lam x e) with no annotation
+    Lam(String, Option<SourceTy>, Box<SourceExp>),
+    App(Box<SourceExp>, Box<SourceExp>),
+}
+
+//Prints the expression's S-expression syntax, which parse_source
+//parses back
+impl ToString for SourceExp {
+    fn to_string(&self) -> String {
+        match &self.kind {
+            SourceKind::I32(i) => i.to_string(),
+            SourceKind::Bool(b) => b.to_string(),
+            SourceKind::Unit => "tt".to_string(),
+            SourceKind::Var(x) => x.clone(),
+            SourceKind::Unop(Unop::UMinus, e) => format!("(- 0 {})", e.to_string()),
+            SourceKind::Unop(op, e) => format!("({} {})", op.to_string(), e.to_string()),
+            SourceKind::Binop(op, e1, e2) => format!("({} {} {})", op.to_string(), e1.to_string(), e2.to_string()),
+            SourceKind::Let(x, e1, e2) => format!("(let {} {} {})", x, e1.to_string(), e2.to_string()),
+            SourceKind::Seq(e1, e2) => format!("(seq {} {})", e1.to_string(), e2.to_string()),
+            SourceKind::Alloc(e1, e2) => format!("(alloc {} {})", e1.to_string(), e2.to_string()),
+            SourceKind::Set(e1, e2, e3) => format!("(set {} {} {})", e1.to_string(), e2.to_string(), e3.to_string()),
+            SourceKind::Get(e1, e2) => format!("(get {} {})", e1.to_string(), e2.to_string()),
+            SourceKind::Cond(e1, e2, e3) => format!("(cond {} {} {})", e1.to_string(), e2.to_string(), e3.to_string()),
+            SourceKind::Lam(x, Some(ty), e) => format!("(lam {} {} {})", x, ty.to_string(), e.to_string()),
+            SourceKind::Lam(x, None, e) => format!("(lam {} {})", x, e.to_string()),
+            SourceKind::App(e1, e2) => format!("(app {} {})", e1.to_string(), e2.to_string())
+        }
+    }
+}
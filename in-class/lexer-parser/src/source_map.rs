@@ -0,0 +1,141 @@
+use lexer::{LineInfo,Span,TAB_WIDTH};
+
+/* A SourceMap maps byte offsets in a program back to the positions the
+   lexer would have reported for them, and renders spans against the
+   program text for error messages. Line breaks are \n, \r\n or a lone
+   \r, as in the lexer. */
+pub struct SourceMap<'a> {
+    src: &'a str,
+    //Byte offset at which each line starts; line n starts at line_starts[n-1]
+    line_starts: Vec<usize>,
+}
+
+impl<'a> SourceMap<'a> {
+    pub fn new(src: &'a str) -> SourceMap<'a> {
+        let bytes = src.as_bytes();
+        let mut line_starts = vec![0];
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\r' if bytes.get(i + 1) == Some(&b'\n') => { i += 2; line_starts.push(i) },
+                b'\r' | b'\n' => { i += 1; line_starts.push(i) },
+                _ => i += 1
+            }
+        }
+        SourceMap{src, line_starts}
+    }
+
+    //The number of lines in the source
+    pub fn num_lines(&self) -> u64 {
+        self.line_starts.len() as u64
+    }
+
+    //The position of the byte at offset. Offsets partway through a
+    //character or line break are rounded down; offsets past the end of
+    //the source are clamped to it.
+    pub fn info_at(&self, offset: u64) -> LineInfo {
+        let offset = (offset as usize).min(self.src.len());
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(i) => i,
+            Err(i) => i - 1
+        };
+        let mut info = LineInfo::start();
+        info.line_no = line as u64 + 1;
+        info.byte_offset = self.line_starts[line] as u64;
+        for c in self.line(info.line_no).chars() {
+            if info.byte_offset + c.len_utf8() as u64 > offset as u64 { break }
+            info.incr_char(c)
+        }
+        info
+    }
+
+    //The (line, character column) of the byte at offset
+    pub fn lookup(&self, offset: u64) -> (u64, u64) {
+        let info = self.info_at(offset);
+        (info.line_no, info.col_no)
+    }
+
+    //The text of line n (counting from 1), without its line break
+    pub fn line(&self, n: u64) -> &'a str {
+        let i = (n as usize).saturating_sub(1).min(self.line_starts.len() - 1);
+        let start = self.line_starts[i];
+        let end = self.line_starts.get(i + 1).map_or(self.src.len(), |&e| e);
+        self.src[start..end].trim_end_matches(['\r', '\n'])
+    }
+
+    //Render msg with the line span starts on, underlining the span.
+    //Tabs are expanded so the underline lines up with the text.
+    pub fn render(&self, span: &Span, msg: &str) -> String {
+        let mut text = String::new();
+        let mut col = 0;
+        for c in self.line(span.start.line_no).chars() {
+            if c == '\t' {
+                let next = (col / TAB_WIDTH + 1) * TAB_WIDTH;
+                text.extend((col..next).map(|_| ' '));
+                col = next
+            } else {
+                let mut info = LineInfo::start();
+                info.incr_char(c);
+                text.push(c);
+                col += info.display_col
+            }
+        }
+        //Spans running onto later lines are underlined to the end of this one
+        let end = if span.end.line_no == span.start.line_no { span.end.display_col } else { col };
+        let width = end.saturating_sub(span.start.display_col).max(1);
+        let gutter = span.start.line_no.to_string();
+        format!("{}:{}: {}\n{} | {}\n{} | {}{}",
+                span.start.line_no, span.start.col_no, msg,
+                gutter, text,
+                " ".repeat(gutter.len()), " ".repeat(span.start.display_col as usize), "^".repeat(width as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::{LexError,LexerState};
+
+    #[test]
+    fn lookup_offsets() {
+        let src = "ab\r\ncé\rx\n\n";
+        let map = SourceMap::new(src);
+        assert_eq!(map.num_lines(), 5);
+        assert_eq!(map.lookup(0), (1, 0));
+        assert_eq!(map.lookup(3), (1, 2));
+        assert_eq!(map.lookup(4), (2, 0));
+        assert_eq!(map.lookup(6), (2, 1)); //partway through é
+        assert_eq!(map.lookup(7), (2, 2));
+        assert_eq!(map.lookup(8), (3, 0));
+        assert_eq!(map.lookup(100), (5, 0));
+        assert_eq!(map.line(2), "cé");
+        assert_eq!(map.line(4), "");
+    }
+
+    #[test]
+    fn agrees_with_lexer() {
+        let src = "/* 漢字 */\t1 +\r\n  é";
+        let map = SourceMap::new(src);
+        let mut l = LexerState::new(src);
+        let (_, span) = l.next_spanned().unwrap();
+        assert_eq!(map.info_at(span.start.byte_offset), span.start);
+        l.next().unwrap();
+        let err = l.next().unwrap_err();
+        let span = err.span().clone();
+        assert_eq!(map.info_at(span.start.byte_offset), span.start);
+        assert!(matches!(err, LexError::UnexpectedChar('é', _)));
+        assert_eq!(map.render(&span, "unexpected character"),
+                   "2:2: unexpected character\n2 |   é\n  |   ^");
+    }
+
+    #[test]
+    fn render_wide_and_tabs() {
+        let src = "\t漢 foo";
+        let map = SourceMap::new(src);
+        let start = map.info_at(5);
+        let end = map.info_at(8);
+        assert_eq!((start.col_no, start.display_col), (3, 11));
+        assert_eq!(map.render(&Span{start, end}, "here"),
+                   "1:3: here\n1 |         漢 foo\n  |            ^^^");
+    }
+}
@@ -0,0 +1,259 @@
+use ir_parser::{binop_of,eat,err,parse_id,span_from,Res};
+use lexer::{LexError,LexerState,Span,Tok,TokenStream};
+use lexer::Tok::*;
+use source::*;
+use types::Unop;
+
+/* GRAMMAR (see doc/source.md):
+
+    <exp> ::= <i32> | true | false | tt | <id>
+            | ( neg <exp> )
+            | ( <binop> <exp> <exp> )
+            | ( let <id> <exp> <exp> )
+            | ( seq <exp> <exp> )
+            | ( alloc <exp> <exp> )
+            | ( set <exp> <exp> <exp> )
+            | ( get <exp> <exp> )
+            | ( cond <exp> <exp> <exp> )
+            | ( lam <id> <ty> <exp> )
+            | ( lam <id> <exp> )
+            | ( app <exp> <exp> )
+     <ty> ::= i32 | bool | unit | ( array <ty> ) | ( -> <ty> <ty> )
+
+   The unannotated ( lam <id> <exp> ) isn't in doc/source.md, which
+   requires every lam's annotation; its type is inferred (infer.rs).
+
+   lam and app aren't GrumpyIR keywords, so the lexer returns them as
+   identifiers; they're keywords only after a ( here. Whether a lam is
+   annotated is decided by the token after its variable, or if that's a
+   (, by the token after that: a ( followed by an operator, keyword, lam
+   or app starts an expression, and any other ( a type. The operands of
+   each form are parsed up to its ), so that a form with too few or too
+   many is reported as such. */
+
+pub fn parse_ty<L: TokenStream>(l: &mut L) -> Res<SourceTy> {
+    match l.next_spanned()? {
+        (I32TY, _) => Ok(SourceTy::I32),
+        (BOOLTY, _) => Ok(SourceTy::Bool),
+        (UNITTY, _) => Ok(SourceTy::Unit),
+        (LPAREN, _) => parse_compound_ty(l),
+        (tok, span) => err(format!("expected a type, found {:?}", tok), span)
+    }
+}
+
+//Parse the rest of an array or function type, after its (
+fn parse_compound_ty<L: TokenStream>(l: &mut L) -> Res<SourceTy> {
+    let t = match l.next_spanned()? {
+        (ARRAYTY, _) => SourceTy::Array(Box::new(parse_ty(l)?)),
+        (ARROW, _) => {
+            let t1 = parse_ty(l)?;
+            SourceTy::Fun(Box::new(t1), Box::new(parse_ty(l)?))
+        },
+        (tok, span) => return err(format!("expected array or -> in a type, found {:?}", tok), span)
+    };
+    eat(l, RPAREN)?;
+    Ok(t)
+}
+
+//Parse the annotation of a lam if there is one. If not, the first
+//operand is parsed instead and returned in es.
+fn parse_annotation<L: TokenStream>(l: &mut L, es: &mut Vec<SourceExp>) -> Res<Option<SourceTy>> {
+    match l.peek_spanned()?.0 {
+        I32TY | BOOLTY | UNITTY => parse_ty(l).map(Some),
+        LPAREN => {
+            let (_, open) = l.next_spanned()?;
+            if starts_form(&l.peek_spanned()?.0) {
+                es.push(parse_form(l, open)?);
+                Ok(None)
+            } else {
+                parse_compound_ty(l).map(Some)
+            }
+        },
+        _ => Ok(None)
+    }
+}
+
+//Does tok, after a (, start one of the forms parse_form parses?
+fn starts_form(tok: &Tok) -> bool {
+    binop_of(tok).is_some() || match tok {
+        NEG | LET | SEQ | ALLOC | SET | GET | COND => true,
+        ID(f) => f == "lam" || f == "app",
+        _ => false
+    }
+}
+
+pub fn parse_exp<L: TokenStream>(l: &mut L) -> Res<SourceExp> {
+    let (tok, span) = l.next_spanned()?;
+    let kind = match tok {
+        I32(i) => SourceKind::I32(i),
+        TRUE => SourceKind::Bool(true),
+        FALSE => SourceKind::Bool(false),
+        TT => SourceKind::Unit,
+        ID(x) => SourceKind::Var(x),
+        LPAREN => return parse_form(l, span),
+        tok => return err(format!("expected an expression, found {:?}", tok), span)
+    };
+    Ok(SourceExp{kind, span})
+}
+
+//Parse the operands of form, which starts at open, up to its ),
+//checking that there are n of them.
+fn parse_operands<L: TokenStream>(l: &mut L, form: &str, n: usize, open: &Span) -> Res<Vec<SourceExp>> {
+    parse_more_operands(l, form, n, open, vec![])
+}
+
+//Like parse_operands, when the operands es have already been parsed
+fn parse_more_operands<L: TokenStream>(l: &mut L, form: &str, n: usize, open: &Span,
+                                       mut es: Vec<SourceExp>) -> Res<Vec<SourceExp>> {
+    loop {
+        let (tok, span) = l.peek_spanned()?;
+        if tok == RPAREN {
+            if es.len() != n {
+                return err(format!("{} expects {} operand(s), found {}", form, n, es.len()), span_from(open, &span))
+            }
+            return Ok(es)
+        }
+        es.push(parse_exp(l)?)
+    }
+}
+
+//Parse the rest of an expression starting with (, whose span is open
+fn parse_form<L: TokenStream>(l: &mut L, open: Span) -> Res<SourceExp> {
+    let (tok, span) = l.next_spanned()?;
+    let kind =
+        if let Some(op) = binop_of(&tok) {
+            let mut es = parse_operands(l, &op.to_string(), 2, &open)?.into_iter().map(Box::new);
+            SourceKind::Binop(op, es.next().unwrap(), es.next().unwrap())
+        } else {
+            match tok {
+                NEG => {
+                    let mut es = parse_operands(l, "neg", 1, &open)?.into_iter().map(Box::new);
+                    SourceKind::Unop(Unop::UNeg, es.next().unwrap())
+                },
+                LET => {
+                    let x = parse_id(l)?;
+                    let mut es = parse_operands(l, "let", 2, &open)?.into_iter().map(Box::new);
+                    SourceKind::Let(x, es.next().unwrap(), es.next().unwrap())
+                },
+                SEQ => {
+                    let mut es = parse_operands(l, "seq", 2, &open)?.into_iter().map(Box::new);
+                    SourceKind::Seq(es.next().unwrap(), es.next().unwrap())
+                },
+                ALLOC => {
+                    let mut es = parse_operands(l, "alloc", 2, &open)?.into_iter().map(Box::new);
+                    SourceKind::Alloc(es.next().unwrap(), es.next().unwrap())
+                },
+                SET => {
+                    let mut es = parse_operands(l, "set", 3, &open)?.into_iter().map(Box::new);
+                    SourceKind::Set(es.next().unwrap(), es.next().unwrap(), es.next().unwrap())
+                },
+                GET => {
+                    let mut es = parse_operands(l, "get", 2, &open)?.into_iter().map(Box::new);
+                    SourceKind::Get(es.next().unwrap(), es.next().unwrap())
+                },
+                COND => {
+                    let mut es = parse_operands(l, "cond", 3, &open)?.into_iter().map(Box::new);
+                    SourceKind::Cond(es.next().unwrap(), es.next().unwrap(), es.next().unwrap())
+                },
+                ID(ref f) if f == "lam" => {
+                    let x = parse_id(l)?;
+                    let mut es = vec![];
+                    let ty = parse_annotation(l, &mut es)?;
+                    let mut es = parse_more_operands(l, "lam", 1, &open, es)?.into_iter().map(Box::new);
+                    SourceKind::Lam(x, ty, es.next().unwrap())
+                },
+                ID(ref f) if f == "app" => {
+                    let mut es = parse_operands(l, "app", 2, &open)?.into_iter().map(Box::new);
+                    SourceKind::App(es.next().unwrap(), es.next().unwrap())
+                },
+                tok => return err(format!("expected an operator or keyword, found {:?}", tok), span)
+            }
+        };
+    let close = eat(l, RPAREN)?;
+    Ok(SourceExp{kind, span: span_from(&open, &close)})
+}
+
+//Parse a source program, a single expression, from l.
+pub fn parse_source_from<L: TokenStream>(l: &mut L) -> Res<SourceExp> {
+    let e = parse_exp(l)?;
+    match l.peek_spanned() {
+        Err(LexError::UnexpectedEof(_)) => Ok(e),
+        Ok((tok, span)) => err(format!("expected end of program, found {:?}", tok), span),
+        Err(e) => Err(e.into())
+    }
+}
+
+pub fn parse_source(s: &str) -> Res<SourceExp> {
+    parse_source_from(&mut LexerState::new(s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::Binop::*;
+
+    fn kind(s: &str) -> SourceKind {
+        parse_source(s).unwrap().kind
+    }
+
+    fn err_at(s: &str) -> (String, u64, u64) {
+        let err = parse_source(s).unwrap_err();
+        (err.msg, err.span.start.line_no, err.span.start.col_no)
+    }
+
+    #[test]
+    fn lam_and_app() {
+        let s = "(let compose (lam f (-> i32 bool) (lam g (-> (array i32) i32) (lam x (array i32) (app f (app g x)))))\n\
+                 (app (app compose (lam b i32 (< b 0))) (lam a (array i32) (get a 0))))";
+        let e = parse_source(s).unwrap();
+        let lam = match e.kind {
+            SourceKind::Let(ref x, ref lam, _) => { assert_eq!(x, "compose"); lam },
+            ref kind => panic!("unexpected {:?}", kind)
+        };
+        match lam.kind {
+            SourceKind::Lam(ref f, Some(ref ty), _) => {
+                assert_eq!(f, "f");
+                assert_eq!(ty.to_string(), "(-> i32 bool)")
+            },
+            ref kind => panic!("unexpected {:?}", kind)
+        }
+        assert_eq!(lam.span.start.col_no, 13);
+        assert!(matches!(kind("(lam f (-> (-> i32 i32) (array (-> unit bool))) tt)"),
+                         SourceKind::Lam(_, Some(SourceTy::Fun(..)), _)));
+        assert!(matches!(kind("(app (lam x i32 (+ x 1)) 2)"), SourceKind::App(..)));
+    }
+
+    #[test]
+    fn unannotated_lam() {
+        for s in ["(lam x x)", "(lam x (+ x 1))", "(lam x (lam y (app x y)))", "(lam f (app f tt))"].iter() {
+            let e = parse_source(s).unwrap();
+            assert!(matches!(e.kind, SourceKind::Lam(_, None, _)), "{}", s);
+            assert_eq!(e.to_string(), *s);
+        }
+        let s = "(let f (lam a (array i32) (lam x (get a x))) (app (app f (alloc 1 (neg true))) 0))";
+        assert_eq!(parse_source(s).unwrap().to_string(), s);
+        assert_eq!(err_at("(lam x)"), ("lam expects 1 operand(s), found 0".to_string(), 1, 0));
+        assert_eq!(err_at("(lam x (+ x 1) 2)"), ("lam expects 1 operand(s), found 2".to_string(), 1, 0));
+        assert_eq!(err_at("(lam x i32)"), ("lam expects 1 operand(s), found 0".to_string(), 1, 0));
+        assert_eq!(err_at("(lam x (array (list i32)) x)"), ("expected array or -> in a type, found ID(\"list\")".to_string(), 1, 15));
+    }
+
+    #[test]
+    fn ir_forms() {
+        assert!(matches!(kind("(seq (set (alloc 2 true) 0 (neg false)) (cond (== 1 2) tt tt))"), SourceKind::Seq(..)));
+        assert!(matches!(kind("(- x -1)"), SourceKind::Binop(BMinus, ..)));
+        assert_eq!(kind("lam"), SourceKind::Var("lam".to_string()));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(err_at("(app f)"), ("app expects 2 operand(s), found 1".to_string(), 1, 0));
+        assert_eq!(err_at("(lam x i32\n  1 2)"), ("lam expects 1 operand(s), found 2".to_string(), 1, 0));
+        assert_eq!(err_at("(+ 1\n (get a 1 2))"), ("get expects 2 operand(s), found 3".to_string(), 2, 1));
+        assert_eq!(err_at("(lam x (-> i32) x)"), ("expected a type, found RPAREN".to_string(), 1, 14));
+        assert_eq!(err_at("(lam x (list i32) x)"), ("expected array or -> in a type, found ID(\"list\")".to_string(), 1, 8));
+        assert_eq!(err_at("(lam x (array i32 bool) x)"), ("expected RPAREN, found BOOLTY".to_string(), 1, 18));
+        assert_eq!(err_at("(fun f -> i32 1)"), ("expected an operator or keyword, found FUN".to_string(), 1, 1));
+        assert_eq!(err_at("1 2"), ("expected end of program, found I32(2)".to_string(), 1, 2));
+    }
+}
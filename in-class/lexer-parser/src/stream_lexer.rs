@@ -0,0 +1,186 @@
+use std::io::{self,BufRead,BufReader,Read};
+
+use lexer::{lex,Context,LexError,LineInfo,Source,Span,Tok,TokenStream,Vocab};
+
+/* A lexer that pulls its input incrementally from a BufRead, e.g.
+   stdin, rather than borrowing the whole program as a &str. Only the
+   input not yet consumed is buffered, and that window never grows past
+   WINDOW bytes: a token that doesn't fit is a ReadError, while comments
+   of any length are skipped a window at a time. */
+
+const WINDOW: usize = 64 * 1024;
+
+type Lexed<T> = Result<(T, Span), LexError>;
+
+struct ReadSource<R> {
+    reader: R,
+    buf: Vec<u8>,
+    //Start of the unconsumed input in buf
+    pos: usize,
+}
+
+impl<R: BufRead> Source for ReadSource<R> {
+    fn window(&self) -> &[u8] {
+        self.buf.split_at(self.pos).1
+    }
+
+    fn fill(&mut self) -> io::Result<bool> {
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        if self.buf.len() >= WINDOW {
+            return Err(io::Error::other(format!("token longer than {} bytes", WINDOW)))
+        }
+        let n = loop {
+            match self.reader.fill_buf() {
+                Ok(avail) => {
+                    let n = avail.len().min(WINDOW - self.buf.len());
+                    self.buf.extend_from_slice(avail.split_at(n).0);
+                    break n
+                },
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {},
+                Err(err) => return Err(err)
+            }
+        };
+        self.reader.consume(n);
+        Ok(n > 0)
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.pos += n
+    }
+}
+
+pub struct StreamLexer<R, T = Tok> {
+    src: ReadSource<R>,
+    cx: Context,
+    pub info: LineInfo,
+    //The result of lexing ahead for peek, with the position after it
+    peeked: Option<(Lexed<T>, LineInfo)>,
+    //As in LexerState
+    infix: bool,
+    after_operand: bool,
+}
+
+impl<R: Read> StreamLexer<BufReader<R>> {
+    pub fn from_reader(reader: R) -> Self {
+        StreamLexer::init(BufReader::new(reader))
+    }
+}
+
+impl<R: BufRead> StreamLexer<R> {
+    pub fn new(reader: R) -> Self {
+        StreamLexer::init(reader)
+    }
+
+    //A lexer for infix expressions (see LexerState::infix)
+    pub fn infix(reader: R) -> Self {
+        StreamLexer{infix: true, ..StreamLexer::init(reader)}
+    }
+}
+
+impl<R: BufRead, T: Vocab> StreamLexer<R, T> {
+    //A lexer for reader in vocabulary T
+    pub fn init(reader: R) -> Self {
+        StreamLexer{
+            src: ReadSource{reader, buf: vec![], pos: 0},
+            cx: Context::new(),
+            info: LineInfo::start(),
+            peeked: None,
+            infix: false,
+            after_operand: false,
+        }
+    }
+
+    //Like peek, but also returns the span of the token.
+    pub fn peek_spanned(&mut self) -> Result<(T, Span), LexError> {
+        if self.peeked.is_none() {
+            let mut info = self.info.clone();
+            self.cx.after_operand = self.infix && self.after_operand;
+            let res = lex(&mut self.src, &mut info, &mut self.cx);
+            self.peeked = Some((res, info))
+        }
+        self.peeked.as_ref().unwrap().0.clone()
+    }
+
+    pub fn peek(&mut self) -> Result<T, LexError> {
+        self.peek_spanned().map(|(tok, _)| tok)
+    }
+
+    //Like next, but also returns the span of the token.
+    pub fn next_spanned(&mut self) -> Result<(T, Span), LexError> {
+        let res = match self.peeked.take() {
+            Some((res, info)) => {
+                self.info = info;
+                res
+            },
+            None => {
+                self.cx.after_operand = self.infix && self.after_operand;
+                lex(&mut self.src, &mut self.info, &mut self.cx)
+            }
+        };
+        if let Ok((ref tok, _)) = res { self.after_operand = tok.ends_operand() }
+        res
+    }
+
+    pub fn next(&mut self) -> Result<T, LexError> {
+        self.next_spanned().map(|(tok, _)| tok)
+    }
+}
+
+impl<R: BufRead> TokenStream for StreamLexer<R> {
+    fn peek_spanned(&mut self) -> Result<(Tok, Span), LexError> {
+        StreamLexer::peek_spanned(self)
+    }
+
+    fn next_spanned(&mut self) -> Result<(Tok, Span), LexError> {
+        StreamLexer::next_spanned(self)
+    }
+
+    fn info(&self) -> LineInfo {
+        self.info.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::LexerState;
+    use lexer::LexError::*;
+
+    fn all_tokens<L: TokenStream>(l: &mut L) -> Vec<Result<(Tok, Span), LexError>> {
+        let mut toks = vec![];
+        loop {
+            let res = l.next_spanned();
+            toks.push(res.clone());
+            if res.is_err() { break }
+        }
+        toks
+    }
+
+    #[test]
+    fn stream_matches_str() {
+        //Multi-byte characters straddle the window edges in the comments
+        let long_line = "xé漢😀".repeat(WINDOW / 3);
+        let s = format!("(fun f (x i32) -> i32 // {}\n /* {} /* // */ */ (+ x -0x10))\n%\n(f 3)",
+                        long_line, long_line);
+        //Read one byte at a time so that every token straddles a refill
+        let mut stream = StreamLexer::new(BufReader::with_capacity(1, s.as_bytes()));
+        let mut str_lexer = LexerState::new(&s);
+        let expected = all_tokens(&mut str_lexer);
+        assert_eq!(expected.len(), 21);
+        assert_eq!(all_tokens(&mut stream), expected);
+    }
+
+    #[test]
+    fn stream_errors() {
+        let s = format!("1 {}", "y".repeat(WINDOW + 1));
+        let mut l = StreamLexer::from_reader(s.as_bytes());
+        assert_eq!(l.next(), Ok(Tok::I32(1)));
+        assert!(matches!(l.next(), Err(ReadError(..))));
+
+        let mut l = StreamLexer::new(BufReader::with_capacity(1, "1 é".as_bytes()));
+        assert_eq!(l.peek(), Ok(Tok::I32(1)));
+        l.next().unwrap();
+        assert!(matches!(l.next(), Err(UnexpectedChar('é', _))));
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use ir::*;
+use lexer::Span;
+use types::{Binop,Ty,Unop};
+use types::Ty::*;
+
+/********************************************
+ * Type checking GrumpyIR
+ ********************************************/
+
+/* A type checker for GrumpyIR programs, following the typing rules of
+   doc/source.md (T-i32, T-binop-i32, T-let and so on) for the forms the
+   two languages share, extended to functions:
+
+   - (funptr f) has type (-> (ty1 ... tyN) ty) when f is declared
+     (fun f (x1 ty1) ... (xN tyN) -> ty e), and (call e e1 ... eN) and
+     (f e1 ... eN) take arguments of those types to a result of type ty.
+   - Each function's body must have its declared return type, given
+     that its parameters have their declared types.
+   - (print e) takes an i32 to unit; (spawn e) takes the address of a
+     closure, an array, to unit. Closures aren't otherwise typed.
+
+   Two rules differ from doc/source.md. T-let there omits its first
+   premise, G |- e1 : ty1. And T-seq there requires e1 : unit, but the
+   value of e1 is discarded, and GrumpyIR programs like pa/tests/seq.gpy,
+   (seq 3 4), sequence expressions of other types, so here e1 may have
+   any type.
+
+   The checker doesn't stop at the first error. An expression with an
+   error is given no type, and no errors are reported for expressions
+   that use it, so each mistake is reported once. */
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct TypeError {
+    pub msg: String,
+    pub span: Span,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}:{}", self.msg, self.span.start.line_no, self.span.start.col_no)
+    }
+}
+
+/* The types of a program's functions and expressions. No two
+   expressions have the same span, so expressions are keyed by the byte
+   offsets of theirs. */
+#[derive(Debug,Clone,PartialEq)]
+pub struct TypeMap {
+    pub funs: HashMap<String, Ty>,
+    exps: HashMap<(u64, u64), Ty>,
+}
+
+impl TypeMap {
+    //The type of e, an expression in the program checked
+    pub fn type_of(&self, e: &Exp) -> Option<&Ty> {
+        self.exps.get(&(e.span.start.byte_offset, e.span.end.byte_offset))
+    }
+}
+
+struct Checker<'p> {
+    types: TypeMap,
+    errors: Vec<TypeError>,
+    //Variables in scope, innermost last
+    env: Vec<(&'p str, Ty)>,
+}
+
+impl<'p> Checker<'p> {
+    fn error(&mut self, msg: String, span: &Span) -> Option<Ty> {
+        self.errors.push(TypeError{msg, span: span.clone()});
+        None
+    }
+
+    //Check that e has type expected.
+    fn expect(&mut self, e: &'p Exp, expected: &Ty) -> Option<()> {
+        let ty = self.check(e)?;
+        if ty == *expected { return Some(()) }
+        self.error(format!("expected {}, found {}", expected.to_string(), ty.to_string()), &e.span);
+        None
+    }
+
+    //Check that e has an array type, returning its element type.
+    fn expect_array(&mut self, e: &'p Exp) -> Option<Ty> {
+        match self.check(e)? {
+            TyArray(t) => Some(*t),
+            ty => self.error(format!("expected an array, found {}", ty.to_string()), &e.span)
+        }
+    }
+
+    //The result of calling a function of type fun on args, whose types
+    //are tys
+    fn apply(&mut self, fun: Ty, args: &[Exp], tys: Vec<Option<Ty>>, span: &Span) -> Option<Ty> {
+        let (params, ret) = match fun {
+            TyFun(params, ret) => (params, ret),
+            ty => return self.error(format!("expected a function pointer, found {}", ty.to_string()), span)
+        };
+        if args.len() != params.len() {
+            return self.error(format!("expected {} argument(s), found {}", params.len(), args.len()), span)
+        }
+        let mut ok = true;
+        for ((e, ty), param) in args.iter().zip(tys).zip(params.iter()) {
+            match ty {
+                Some(ty) if ty != *param => {
+                    self.error(format!("expected {}, found {}", param.to_string(), ty.to_string()), &e.span);
+                    ok = false
+                },
+                None => ok = false,
+                Some(_) => {}
+            }
+        }
+        if ok { Some(*ret) } else { None }
+    }
+
+    fn fun_type(&mut self, f: &str, span: &Span) -> Option<Ty> {
+        match self.types.funs.get(f) {
+            Some(ty) => Some(ty.clone()),
+            None => self.error(format!("unknown function {}", f), span)
+        }
+    }
+
+    //The type of e, recording it and those of its subexpressions.
+    //None if e has a type error.
+    fn check(&mut self, e: &'p Exp) -> Option<Ty> {
+        let ty = self.check_kind(e);
+        if let Some(ref ty) = ty {
+            self.types.exps.insert((e.span.start.byte_offset, e.span.end.byte_offset), ty.clone());
+        }
+        ty
+    }
+
+    fn check_kind(&mut self, e: &'p Exp) -> Option<Ty> {
+        let span = &e.span;
+        match &e.kind {
+            ExpKind::I32(_) => Some(TyI32),
+            ExpKind::Bool(_) => Some(TyBool),
+            ExpKind::Unit => Some(TyUnit),
+            ExpKind::Var(x) => match self.env.iter().rev().find(|(y, _)| y == x) {
+                Some((_, ty)) => Some(ty.clone()),
+                None => self.error(format!("unbound variable {}", x), span)
+            },
+            ExpKind::Unop(Unop::UNeg, e) => self.expect(e, &TyBool).map(|_| TyBool),
+            ExpKind::Unop(Unop::UMinus, e) => self.expect(e, &TyI32).map(|_| TyI32),
+            ExpKind::Binop(op, e1, e2) => {
+                let (ok1, ok2) = (self.expect(e1, &TyI32), self.expect(e2, &TyI32));
+                ok1?;
+                ok2?;
+                match op {
+                    Binop::BLt | Binop::BEq => Some(TyBool),
+                    _ => Some(TyI32)
+                }
+            },
+            ExpKind::Let(x, e1, e2) => {
+                //If e1 has an error, x's uses in e2 can't be checked
+                let ty1 = self.check(e1)?;
+                self.env.push((x, ty1));
+                let ty2 = self.check(e2);
+                self.env.pop();
+                ty2
+            },
+            ExpKind::Seq(e1, e2) => {
+                let ty1 = self.check(e1);
+                let ty2 = self.check(e2);
+                ty1.and(ty2)
+            },
+            ExpKind::Alloc(esize, einit) => {
+                let size = self.expect(esize, &TyI32);
+                let ty = self.check(einit)?;
+                size.map(|_| TyArray(Box::new(ty)))
+            },
+            ExpKind::Set(earr, eidx, e) => {
+                let ty = self.expect_array(earr);
+                let idx = self.expect(eidx, &TyI32);
+                let v = match ty {
+                    Some(ref ty) => self.expect(e, ty),
+                    None => self.check(e).map(|_| ())
+                };
+                ty.and(idx).and(v).map(|_| TyUnit)
+            },
+            ExpKind::Get(earr, eidx) => {
+                let ty = self.expect_array(earr);
+                self.expect(eidx, &TyI32)?;
+                ty
+            },
+            ExpKind::Cond(econd, e1, e2) => {
+                let cond = self.expect(econd, &TyBool);
+                let (ty1, ty2) = (self.check(e1), self.check(e2));
+                let (ty1, ty2) = (ty1?, ty2?);
+                if ty1 != ty2 {
+                    return self.error(format!("cond branches have different types, {} and {}",
+                                              ty1.to_string(), ty2.to_string()), span)
+                }
+                cond.map(|_| ty1)
+            },
+            ExpKind::Funptr(f) => self.fun_type(f, span),
+            ExpKind::Call(ef, args) => {
+                //As compiled, the arguments come first
+                let tys = args.iter().map(|e| self.check(e)).collect();
+                let fun = self.check(ef)?;
+                self.apply(fun, args, tys, span)
+            },
+            ExpKind::CallFun(f, args) => {
+                let fun = self.fun_type(f, span);
+                let tys = args.iter().map(|e| self.check(e)).collect();
+                self.apply(fun?, args, tys, span)
+            },
+            ExpKind::Print(e) => self.expect(e, &TyI32).map(|_| TyUnit),
+            ExpKind::Spawn(e) => self.expect_array(e).map(|_| TyUnit),
+        }
+    }
+}
+
+pub fn typecheck(prog: &Program) -> Result<TypeMap, Vec<TypeError>> {
+    let mut c = Checker{types: TypeMap{funs: HashMap::new(), exps: HashMap::new()}, errors: vec![], env: vec![]};
+    for f in prog.funs.iter() {
+        if c.types.funs.contains_key(&f.name) {
+            c.error(format!("function {} is defined more than once", f.name), &f.span);
+            continue
+        }
+        let ty = TyFun(f.params.iter().map(|p| p.ty.clone()).collect(), Box::new(f.ret_ty.clone()));
+        c.types.funs.insert(f.name.clone(), ty);
+    }
+    for f in prog.funs.iter() {
+        for (i, p) in f.params.iter().enumerate() {
+            if f.params[..i].iter().any(|q| q.name == p.name) {
+                c.error(format!("duplicate parameter {}", p.name), &p.span);
+            }
+        }
+        c.env = f.params.iter().map(|p| (p.name.as_str(), p.ty.clone())).collect();
+        match c.check(&f.body) {
+            Some(ref ty) if *ty != f.ret_ty => {
+                c.error(format!("{} is declared to return {}, but its body has type {}",
+                                f.name, f.ret_ty.to_string(), ty.to_string()), &f.body.span);
+            },
+            _ => {}
+        }
+    }
+    c.env = vec![];
+    c.check(&prog.main);
+    if c.errors.is_empty() { Ok(c.types) } else { Err(c.errors) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use ir_parser::parse_program;
+
+    fn errors(s: &str) -> Vec<(String, u64, u64)> {
+        match typecheck(&parse_program(s).unwrap()) {
+            Ok(_) => vec![],
+            Err(errs) => errs.into_iter().map(|e| (e.msg, e.span.start.line_no, e.span.start.col_no)).collect()
+        }
+    }
+
+    fn err(msg: &str, line: u64, col: u64) -> (String, u64, u64) {
+        (msg.to_string(), line, col)
+    }
+
+    //The PA4 test programs, whose results are in the .expected files
+    #[test]
+    fn pa4_programs() {
+        let names = ["array", "array2", "array3", "comment", "div", "fact", "fact2", "fib", "fib-memo",
+                     "funptr", "funptr2", "funptr3", "heap", "heap2", "heap3", "let1", "let2", "minus",
+                     "multi-arg", "neg", "plus", "seq", "times"];
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../../pa/tests/");
+        for name in names.iter() {
+            let src = fs::read_to_string(format!("{}{}.gpy", dir, name)).unwrap();
+            let prog = parse_program(&src).unwrap();
+            let types = typecheck(&prog).unwrap_or_else(|errs| panic!("{}: {}", name, errs[0]));
+            let expected = fs::read_to_string(format!("{}{}.expected", dir, name)).unwrap();
+            let ty = types.type_of(&prog.main).unwrap().to_string();
+            let expected_ty = match expected.split('(').next().unwrap() {
+                "Vi32" => "i32",
+                "Vbool" => "bool",
+                _ => "(array"
+            };
+            assert!(ty.starts_with(expected_ty), "{} has type {}, but its result is {}", name, ty, expected);
+        }
+    }
+
+    #[test]
+    fn types_of_expressions() {
+        let s = "(fun f (x i32) (a (array bool)) -> bool (get a x))\n% (let p (funptr f) (seq 1 (call p 0 (alloc 2 true))))";
+        let prog = parse_program(s).unwrap();
+        let types = typecheck(&prog).unwrap();
+        assert_eq!(types.type_of(&prog.main), Some(&TyBool));
+        assert_eq!(types.funs["f"].to_string(), "(-> (i32 (array bool)) bool)");
+        match prog.main.kind {
+            ExpKind::Let(_, ref e1, _) => assert_eq!(types.type_of(e1), Some(&types.funs["f"])),
+            ref kind => panic!("unexpected {:?}", kind)
+        }
+        assert_eq!(errors("% (spawn (alloc 1 (print 0)))"), vec![]);
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert_eq!(errors("% (+ 1 true)"), vec![err("expected i32, found bool", 1, 7)]);
+        assert_eq!(errors("% (neg 1)"), vec![err("expected bool, found i32", 1, 7)]);
+        assert_eq!(errors("% (cond true 1 tt)"), vec![err("cond branches have different types, i32 and unit", 1, 2)]);
+        assert_eq!(errors("% (get (alloc 1 2) true)"), vec![err("expected i32, found bool", 1, 19)]);
+        assert_eq!(errors("% (set 1 0 0)"), vec![err("expected an array, found i32", 1, 7)]);
+        assert_eq!(errors("% (let x y (+ x z))"), vec![err("unbound variable y", 1, 9)]);
+        assert_eq!(errors("% (print (f 1))"), vec![err("unknown function f", 1, 9)]);
+        assert_eq!(errors("(fun f (x i32) -> i32 x) % (f 1 2)"), vec![err("expected 1 argument(s), found 2", 1, 27)]);
+        assert_eq!(errors("(fun f (x i32) -> i32 x) % (call 3 1)"), vec![err("expected a function pointer, found i32", 1, 27)]);
+        //Each mistake is reported once, and checking carries on after it
+        assert_eq!(errors("(fun f (x i32) -> unit\n  (+ x 1))\n(fun f -> i32 1)\n% (seq (- (neg 1) 2) (f true))"), vec![
+            err("function f is defined more than once", 3, 0),
+            err("f is declared to return unit, but its body has type i32", 2, 2),
+            err("expected bool, found i32", 4, 15),
+            err("expected i32, found bool", 4, 24),
+        ]);
+        assert_eq!(errors("(fun g (x i32) (x bool) -> bool x) % 0"), vec![err("duplicate parameter x", 1, 15)]);
+    }
+}
@@ -1,3 +1,4 @@
+use std::fmt;
 use std::string::{ToString};
 
 /********************************************
@@ -10,10 +11,14 @@ trait ToString {
 }
 */
     
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,PartialEq)]
 pub enum Binop {
     BPlus,
     BTimes,
+    BMinus,
+    BDiv,
+    BLt,
+    BEq,
 }
 
 use types::Binop::*;
@@ -22,67 +27,314 @@ impl ToString for Binop {
     fn to_string(&self) -> String {
         match self {
             BPlus => "+".to_string(),
-            BTimes => "*".to_string()
+            BTimes => "*".to_string(),
+            BMinus => "-".to_string(),
+            BDiv => "/".to_string(),
+            BLt => "<".to_string(),
+            BEq => "==".to_string()
         }
     }
 }
 
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,PartialEq)]
+pub enum Unop {
+    UMinus, //Arithmetic negation
+    UNeg,   //Boolean negation
+}
+
+use types::Unop::*;
+
+impl ToString for Unop {
+    fn to_string(&self) -> String {
+        match self {
+            UMinus => "-".to_string(),
+            UNeg => "neg".to_string()
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Unexp {
+    pub op: Unop,
+    pub e: Exp
+}
+
+#[derive(Debug,Clone,PartialEq)]
 pub struct Binexp {
     pub op: Binop,
     pub lhs: Exp,
     pub rhs: Exp
 }
 
+/* Booleans are represented by the i32s 0 (false) and 1 (true): the
+   comparisons return 0 or 1, and neg maps 0 to 1 and anything else to 0. */
+pub type Val = i32;
+
+/* What to do when arithmetic overflows: stop with an error, wrap around
+   (two's complement, as in release builds), or clamp to i32::MIN or
+   i32::MAX. Dividing by zero is an error in every mode. */
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum OverflowMode {
+    Trap,
+    Wrap,
+    Saturate,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum EvalError {
+    Overflow(Binop),
+    DivByZero,
+    //The expression contains an EError
+    SyntaxError,
+    //A GrumpyIR form, like let, that the arithmetic interpreter and the
+    //stack machine compiler don't handle
+    Unsupported(String),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::Overflow(op) => write!(f, "arithmetic overflow in {}", op.to_string()),
+            EvalError::DivByZero => write!(f, "division by zero"),
+            EvalError::SyntaxError => write!(f, "expression has syntax errors"),
+            EvalError::Unsupported(form) => write!(f, "{} isn't supported by the arithmetic interpreter or compiler", form)
+        }
+    }
+}
+
+//Apply op to v1 and v2. The interpreter and the VM both use this, so
+//they agree in every mode.
+pub fn eval_binop(op: &Binop, v1: Val, v2: Val, mode: OverflowMode) -> Result<Val, EvalError> {
+    //The exact result, which may not fit in an i32
+    let (v1, v2) = (v1 as i64, v2 as i64);
+    let v = match op {
+        BPlus => v1 + v2,
+        BTimes => v1 * v2,
+        BMinus => v1 - v2,
+        BDiv if v2 == 0 => return Err(EvalError::DivByZero),
+        BDiv => v1 / v2,
+        BLt => return Ok((v1 < v2) as i32),
+        BEq => return Ok((v1 == v2) as i32),
+    };
+    if (i32::MIN as i64..=i32::MAX as i64).contains(&v) { return Ok(v as i32) }
+    match mode {
+        OverflowMode::Trap => Err(EvalError::Overflow(op.clone())),
+        OverflowMode::Wrap => Ok(v as i32),
+        OverflowMode::Saturate => Ok(v.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+    }
+}
+
 pub trait Interp {
-    fn interp(&self) -> i32;
+    fn interp_with(&self, mode: OverflowMode) -> Result<Val, EvalError>;
+
+    //Interpret, stopping at the first overflow
+    fn interp(&self) -> Result<Val, EvalError> {
+        self.interp_with(OverflowMode::Trap)
+    }
 }
 
-impl Interp for Binexp {
-    fn interp(&self) -> i32 {
+impl Interp for Unexp {
+    fn interp_with(&self, mode: OverflowMode) -> Result<Val, EvalError> {
+        let v = self.e.interp_with(mode)?;
         match self.op {
-            BPlus => self.lhs.interp() + self.rhs.interp(),
-            BTimes => self.lhs.interp() * self.rhs.interp(),
+            //- e is 0 - e, as it's compiled
+            UMinus => eval_binop(&BMinus, 0, v, mode),
+            UNeg => Ok((v == 0) as i32),
         }
     }
 }
 
+impl ToString for Unexp {
+    fn to_string(&self) -> String {
+        match self.op {
+            //GrumpyIR has no arithmetic negation
+            UMinus => format!("(- 0 {})", self.e.to_string()),
+            UNeg => format!("(neg {})", self.e.to_string())
+        }
+    }
+}
+
+impl Interp for Binexp {
+    fn interp_with(&self, mode: OverflowMode) -> Result<Val, EvalError> {
+        let v1 = self.lhs.interp_with(mode)?;
+        let v2 = self.rhs.interp_with(mode)?;
+        eval_binop(&self.op, v1, v2, mode)
+    }
+}
+
 impl ToString for Binexp {
     fn to_string(&self) -> String {
-        format!("({} {} {})", self.lhs.to_string(), self.op.to_string(), self.rhs.to_string())
+        format!("({} {} {})", self.op.to_string(), self.lhs.to_string(), self.rhs.to_string())
     }
 }
 
-#[derive(Debug,Clone)]
+/* The expression language extends to the GrumpyIR expressions of
+   doc/ir.md. The parser in parser.rs produces only the arithmetic forms
+   (EI32, EUnop and EBinop); ir::Exp::lower gives the rest. A call of a
+   function by name, (f e1 ... eN), is represented as the equivalent
+   (call (funptr f) e1 ... eN). */
+
+#[derive(Debug,Clone,PartialEq)]
 pub enum Exp {
     EI32(i32),
+    EBool(bool),
+    EUnit,
+    EId(String),
+    EUnop(Box<Unexp>),
     EBinop(Box<Binexp>),
+    ELet(String, Box<Exp>, Box<Exp>),
+    ESeq(Box<Exp>, Box<Exp>),
+    //(alloc size init)
+    EAlloc(Box<Exp>, Box<Exp>),
+    //(set arr idx e)
+    ESet(Box<Exp>, Box<Exp>, Box<Exp>),
+    //(get arr idx)
+    EGet(Box<Exp>, Box<Exp>),
+    ECond(Box<Exp>, Box<Exp>, Box<Exp>),
+    EFunptr(String),
+    ECall(Box<Exp>, Vec<Exp>),
+    EPrint(Box<Exp>),
+    ESpawn(Box<Exp>),
+    //Stands in for an expression that couldn't be parsed
+    EError,
 }
 
 use types::Exp::*;
 
+impl Exp {
+    //The keyword of e's form, or None for values and variables
+    pub fn keyword(&self) -> Option<&'static str> {
+        match self {
+            ELet(..) => Some("let"),
+            ESeq(..) => Some("seq"),
+            EAlloc(..) => Some("alloc"),
+            ESet(..) => Some("set"),
+            EGet(..) => Some("get"),
+            ECond(..) => Some("cond"),
+            EFunptr(_) => Some("funptr"),
+            ECall(..) => Some("call"),
+            EPrint(_) => Some("print"),
+            ESpawn(_) => Some("spawn"),
+            _ => None
+        }
+    }
+
+    //e in the infix syntax read by parser.rs, fully parenthesized. Forms
+    //that syntax lacks are written as S-expressions.
+    pub fn to_infix(&self) -> String {
+        match self {
+            EUnop(u) => format!("({} {})", u.op.to_string(), u.e.to_infix()),
+            EBinop(b) => format!("({} {} {})", b.lhs.to_infix(), b.op.to_string(), b.rhs.to_infix()),
+            e => e.to_string()
+        }
+    }
+}
+
 impl Interp for Exp {
-    fn interp(&self) -> i32 {
+    fn interp_with(&self, mode: OverflowMode) -> Result<Val, EvalError> {
         match self {
-            EI32(i) => *i,
-            EBinop(b) => b.interp()
+            EI32(i) => Ok(*i),
+            EBool(b) => Ok(*b as i32),
+            EUnop(u) => u.interp_with(mode),
+            EBinop(b) => b.interp_with(mode),
+            EError => Err(EvalError::SyntaxError),
+            e => Err(e.unsupported())
         }
     }
 }
 
+impl Exp {
+    //The error for e, a form outside the arithmetic language
+    pub fn unsupported(&self) -> EvalError {
+        EvalError::Unsupported(match self {
+            EUnit => "tt".to_string(),
+            EId(x) => format!("variable {}", x),
+            e => e.keyword().unwrap_or_default().to_string()
+        })
+    }
+}
+
+//The S-expression (kw e1 ... eN)
+fn sexp(kw: &str, es: &[&Exp]) -> String {
+    let mut s = format!("({}", kw);
+    for e in es.iter() {
+        s.push(' ');
+        s.push_str(&e.to_string())
+    }
+    s.push(')');
+    s
+}
+
 impl ToString for Exp {
     fn to_string(&self) -> String {
+        let kw = self.keyword().unwrap_or_default();
         match self {
             EI32(i) => i.to_string(),
-            EBinop(b) => b.to_string()
+            EBool(b) => b.to_string(),
+            EUnit => "tt".to_string(),
+            EId(x) => x.clone(),
+            EUnop(u) => u.to_string(),
+            EBinop(b) => b.to_string(),
+            ELet(x, e1, e2) => format!("(let {} {} {})", x, e1.to_string(), e2.to_string()),
+            ESeq(e1, e2) | EAlloc(e1, e2) | EGet(e1, e2) => sexp(kw, &[e1, e2]),
+            ESet(e1, e2, e3) | ECond(e1, e2, e3) => sexp(kw, &[e1, e2, e3]),
+            EFunptr(f) => format!("(funptr {})", f),
+            ECall(f, args) => {
+                let mut es = vec![&**f];
+                es.extend(args.iter());
+                sexp(kw, &es)
+            },
+            EPrint(e) | ESpawn(e) => sexp(kw, &[e]),
+            EError => "<error>".to_string()
+        }
+    }
+}
+
+/********************************************
+ * GrumpyIR types
+ ********************************************/
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Ty {
+    TyI32,
+    TyBool,
+    TyUnit,
+    TyArray(Box<Ty>),
+    //The type of (funptr f) for f taking the argument types to the
+    //result type. GrumpyIR programs can't write it.
+    TyFun(Vec<Ty>, Box<Ty>),
+}
+
+use types::Ty::*;
+
+impl ToString for Ty {
+    fn to_string(&self) -> String {
+        match self {
+            TyI32 => "i32".to_string(),
+            TyBool => "bool".to_string(),
+            TyUnit => "unit".to_string(),
+            TyArray(t) => format!("(array {})", t.to_string()),
+            TyFun(args, ret) => {
+                let args: Vec<String> = args.iter().map(|t| t.to_string()).collect();
+                format!("(-> ({}) {})", args.join(" "), ret.to_string())
+            }
         }
     }
 }
 
+/********************************************
+ * Stack machine
+ ********************************************/
+
 #[derive(Debug,Clone)]
 pub enum Instr {
     IPlus,
     ITimes,
+    IMinus,
+    IDiv,
+    ILt,
+    IEq,
+    INeg,
     II32(i32),
 }
 
@@ -92,39 +344,53 @@ use types::Instr::*;
 pub struct VM {
     pub stack: Vec<i32>,
     pub instrs: Vec<Instr>,
-    pub pc: usize
+    pub pc: usize,
+    pub mode: OverflowMode,
 }
 
 impl VM {
     pub fn init(instrs: &[Instr]) -> VM {
+        VM::with_mode(instrs, OverflowMode::Trap)
+    }
+
+    pub fn with_mode(instrs: &[Instr], mode: OverflowMode) -> VM {
         VM {
             stack: vec![],
             instrs: instrs.to_vec(),
-            pc: 0
+            pc: 0,
+            mode
         }            
     }
 
-    pub fn run(&mut self) -> Option<i32> {
+    //Pop two arguments and push the result of applying op to them.
+    fn binop(&mut self, op: Binop, name: &str) -> Result<(), EvalError> {
+        let v2 = self.stack.pop().unwrap_or_else(|| panic!("{}: missing arg v2", name));
+        let v1 = self.stack.pop().unwrap_or_else(|| panic!("{}: missing arg v1", name));
+        self.stack.push(eval_binop(&op, v1, v2, self.mode)?);
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Result<Val, EvalError> {
         'mainloop:loop {
             if self.pc >= self.instrs.len() { break 'mainloop };
             match self.instrs[self.pc] {
-                IPlus => {
-                    let v2 = self.stack.pop().expect("IPlus: missing arg v2");
-                    let v1 = self.stack.pop().expect("IPlus: missing arg v1");
-                    self.stack.push(v1 + v2)
-                },
-                ITimes => {
-                    let v2 = self.stack.pop().expect("ITimes: missing arg v2");
-                    let v1 = self.stack.pop().expect("ITimes: missing arg v1");
-                    self.stack.push(v1 * v2)
+                IPlus => self.binop(BPlus, "IPlus")?,
+                ITimes => self.binop(BTimes, "ITimes")?,
+                IMinus => self.binop(BMinus, "IMinus")?,
+                IDiv => self.binop(BDiv, "IDiv")?,
+                ILt => self.binop(BLt, "ILt")?,
+                IEq => self.binop(BEq, "IEq")?,
+                INeg => {
+                    let v = self.stack.pop().expect("INeg: missing arg");
+                    self.stack.push((v == 0) as i32)
                 },
                 II32(i) => {
                     self.stack.push(i)
                 }
             };
-            self.pc = self.pc + 1
+            self.pc += 1
         }
         let res = self.stack[self.stack.len() - 1];
-        Some(res)
+        Ok(res)
     }
 }